@@ -0,0 +1 @@
+DROP TABLE account_sync_invitations
@@ -0,0 +1,9 @@
+CREATE TABLE account_sync_invitations (
+  id SERIAL PRIMARY KEY,
+  account INTEGER NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+  other_account INTEGER NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+  inviter INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  invitee INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  UNIQUE (account, other_account),
+  CHECK (inviter <> invitee)
+)
@@ -0,0 +1,6 @@
+-- postgres cannot remove values from enums, so the type has to be recreated
+DELETE FROM expense_events WHERE event_target = 'contents';
+ALTER TYPE expense_event_target RENAME TO expense_event_target_old;
+CREATE TYPE expense_event_target AS ENUM ('expense', 'categories', 'receipts', 'transactions');
+ALTER TABLE expense_events ALTER COLUMN event_target TYPE expense_event_target USING event_target::text::expense_event_target;
+DROP TYPE expense_event_target_old;
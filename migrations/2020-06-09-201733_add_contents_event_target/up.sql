@@ -0,0 +1 @@
+ALTER TYPE expense_event_target ADD VALUE 'contents'
@@ -0,0 +1,3 @@
+DROP TABLE budgets;
+
+DROP TYPE budget_period;
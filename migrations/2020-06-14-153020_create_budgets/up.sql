@@ -0,0 +1,12 @@
+CREATE TYPE budget_period AS ENUM ('month', 'quarter', 'year');
+
+CREATE TABLE budgets (
+  id SERIAL PRIMARY KEY,
+  user_id INTEGER NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+  category_id INTEGER NOT NULL REFERENCES categories(id) ON DELETE CASCADE,
+  period budget_period NOT NULL,
+  amount BIGINT NOT NULL CHECK (amount >= 0),
+  rollover BOOLEAN NOT NULL,
+  start TIMESTAMPTZ NOT NULL,
+  UNIQUE (user_id, category_id, period)
+);
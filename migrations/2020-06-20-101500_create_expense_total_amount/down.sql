@@ -0,0 +1 @@
+DROP FUNCTION expense_total_amount(INTEGER, INTEGER)
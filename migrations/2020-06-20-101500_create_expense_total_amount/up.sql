@@ -0,0 +1,27 @@
+-- the total amount of an expense as seen by `viewer`, i.e. the same as `rendering::calculate_total_amount`:
+-- transactions on the accounts of synchronization partners are inverted if the synchronization says so,
+-- fractions are resolved against the sum of all fixed amounts, and only the viewer's (synchronized) accounts count.
+CREATE FUNCTION expense_total_amount(expense INTEGER, viewer INTEGER) RETURNS BIGINT AS $$
+  WITH ts AS (
+    SELECT
+      t.amount,
+      t.fraction,
+      a.user_id = viewer OR s.account1 IS NOT NULL AS counted,
+      CASE WHEN s.invert THEN -1 ELSE 1 END AS sign
+    FROM expense_transactions t
+    JOIN accounts a ON a.id = t.account_id
+    LEFT JOIN account_synchronizations s
+      ON (s.account1 = t.account_id AND s.user1 <> viewer) OR (s.account2 = t.account_id AND s.user2 <> viewer)
+    WHERE t.expense_id = expense
+  ), fixed AS (
+    SELECT COALESCE(SUM(sign * amount), 0)::DOUBLE PRECISION AS total FROM ts
+  )
+  SELECT COALESCE(SUM(
+    CASE
+      WHEN ts.amount IS NOT NULL THEN ts.sign * ts.amount
+      ELSE TRUNC(COALESCE(ts.sign * ts.fraction, 0) * fixed.total)::BIGINT
+    END
+  ), 0)::BIGINT
+  FROM ts, fixed
+  WHERE ts.counted
+$$ LANGUAGE SQL STABLE;
@@ -0,0 +1,11 @@
+DROP TRIGGER categories_search ON categories;
+DROP TRIGGER expense_categories_search ON expense_categories;
+DROP TRIGGER expense_transactions_search ON expense_transactions;
+DROP TRIGGER expenses_search ON expenses;
+
+DROP FUNCTION categories_search_trigger();
+DROP FUNCTION expense_parts_search_trigger();
+DROP FUNCTION expenses_search_trigger();
+DROP FUNCTION refresh_expense_search(INTEGER);
+
+DROP TABLE expense_search;
@@ -0,0 +1,73 @@
+-- full text search over expenses, their transactions and categories (see `web::expenses::query_expenses`).
+-- the documents are kept up to date by the triggers below.
+CREATE TABLE expense_search (
+  expense_id INTEGER PRIMARY KEY REFERENCES expenses(id) ON DELETE CASCADE,
+  document TSVECTOR NOT NULL
+);
+
+CREATE INDEX expense_search_document_idx ON expense_search USING GIN (document);
+
+CREATE FUNCTION refresh_expense_search(expense INTEGER) RETURNS VOID AS $$
+  INSERT INTO expense_search (expense_id, document)
+  SELECT
+    e.id,
+    setweight(to_tsvector('simple', e.title), 'A') ||
+    setweight(to_tsvector('simple', e.store), 'A') ||
+    setweight(to_tsvector('simple', e.description), 'B') ||
+    setweight(to_tsvector('simple', COALESCE((
+      SELECT string_agg(c.name, ' ')
+      FROM expense_categories ec
+      JOIN categories c ON c.id = ec.category_id
+      WHERE ec.expense_id = e.id
+    ), '')), 'B') ||
+    setweight(to_tsvector('simple', e.comments), 'C') ||
+    setweight(to_tsvector('simple', COALESCE((
+      SELECT string_agg(t.statement || ' ' || t.comments || ' ' ||
+        COALESCE(TO_CHAR(ABS(t.amount) / 100.0, 'FM999999999990.00'), ''), ' ')
+      FROM expense_transactions t
+      WHERE t.expense_id = e.id
+    ), '')), 'C')
+  FROM expenses e
+  WHERE e.id = expense
+  ON CONFLICT (expense_id) DO UPDATE SET document = EXCLUDED.document
+$$ LANGUAGE SQL;
+
+CREATE FUNCTION expenses_search_trigger() RETURNS TRIGGER AS $$
+BEGIN
+  PERFORM refresh_expense_search(NEW.id);
+  RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+-- for transactions and categories of expenses, which may also be moved to another expense
+CREATE FUNCTION expense_parts_search_trigger() RETURNS TRIGGER AS $$
+BEGIN
+  IF TG_OP <> 'INSERT' THEN
+    PERFORM refresh_expense_search(OLD.expense_id);
+  END IF;
+  IF TG_OP <> 'DELETE' THEN
+    PERFORM refresh_expense_search(NEW.expense_id);
+  END IF;
+  RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE FUNCTION categories_search_trigger() RETURNS TRIGGER AS $$
+BEGIN
+  PERFORM refresh_expense_search(expense_id)
+  FROM expense_categories
+  WHERE category_id = NEW.id;
+  RETURN NULL;
+END
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER expenses_search AFTER INSERT OR UPDATE ON expenses
+  FOR EACH ROW EXECUTE PROCEDURE expenses_search_trigger();
+CREATE TRIGGER expense_transactions_search AFTER INSERT OR UPDATE OR DELETE ON expense_transactions
+  FOR EACH ROW EXECUTE PROCEDURE expense_parts_search_trigger();
+CREATE TRIGGER expense_categories_search AFTER INSERT OR UPDATE OR DELETE ON expense_categories
+  FOR EACH ROW EXECUTE PROCEDURE expense_parts_search_trigger();
+CREATE TRIGGER categories_search AFTER UPDATE OF name ON categories
+  FOR EACH ROW EXECUTE PROCEDURE categories_search_trigger();
+
+SELECT refresh_expense_search(id) FROM expenses;
@@ -37,9 +37,26 @@ fn main() {
             cli::import::handle(&connection, sub_matches);
         } else if let Some(sub_matches) = matches.subcommand_matches("export") {
             cli::export::handle(&connection, sub_matches);
+        } else if let Some(sub_matches) = matches.subcommand_matches("trash") {
+            cli::trash::handle(&connection, sub_matches, config.trash, &config.receipts);
+        } else if let Some(sub_matches) = matches.subcommand_matches("template") {
+            cli::template::handle(&connection, sub_matches);
+        } else if let Some(sub_matches) = matches.subcommand_matches("check-balances") {
+            cli::check_balances::handle(&connection, sub_matches);
+        } else if let Some(sub_matches) = matches.subcommand_matches("recurrences") {
+            cli::recurrences::handle(&connection, sub_matches);
+        } else if let Some(sub_matches) = matches.subcommand_matches("tax") {
+            cli::tax::handle(&connection, sub_matches);
         } else if let Some(sub_matches) = matches.subcommand_matches("serve") {
             std::mem::drop(connection); // `serve::handle` creates its own connections
-            cli::serve::handle(sub_matches, config.web, config.database, config.verbosity).await;
+            cli::serve::handle(
+                sub_matches,
+                config.web,
+                config.database,
+                config.receipts,
+                config.verbosity,
+            )
+            .await;
         } else {
             cli::build(true)
                 .print_long_help()
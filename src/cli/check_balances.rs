@@ -0,0 +1,79 @@
+use crate::models::*;
+use crate::queries;
+use crate::reports::{self, format_amount};
+use crate::schema::*;
+
+use clap::ArgMatches;
+use clap::{App, Arg, SubCommand};
+use diesel::prelude::*;
+use log::info;
+use prettytable::{cell, row, Table};
+
+pub fn build() -> App<'static, 'static> {
+    SubCommand::with_name("check-balances")
+        .about("List intervals between balances that are not explained by the expenses")
+        .arg(
+            Arg::with_name("user")
+                .long("user")
+                .short("u")
+                .value_name("name")
+                .help("only check the accounts of this user"),
+        )
+}
+
+pub fn handle(connection: &PgConnection, sub_matches: &ArgMatches<'_>) {
+    let mut query = accounts::table
+        .inner_join(users::table)
+        .select((accounts::all_columns, users::name))
+        .order(accounts::id)
+        .into_boxed();
+    if let Some(uname) = sub_matches.value_of("user") {
+        query = query.filter(users::name.eq(uname));
+    }
+    let accs = query
+        .load::<(Account, String)>(connection)
+        .expect("Error loading accounts");
+
+    // synchronized accounts share their balances and transactions, so checking one of them is enough
+    let ids = accs.iter().map(|(a, _)| a.id).collect::<Vec<_>>();
+    let skipped = account_synchronizations::table
+        .filter(account_synchronizations::account1.eq_any(&ids))
+        .select(account_synchronizations::account2)
+        .load::<i32>(connection)
+        .expect("Error loading synchronizations");
+
+    let mut table = Table::new();
+    table.add_row(row![
+        "Account", "User", "From", "To", "Balances", "Expenses", "Missing"
+    ]);
+
+    let mut count = 0;
+    for (acc, uname) in accs.into_iter() {
+        if skipped.contains(&acc.id) {
+            continue;
+        }
+
+        let flows = queries::account_flows(connection, acc.user_id, acc.id)
+            .expect("Error loading transactions");
+        let checkpoints =
+            queries::account_checkpoints(connection, acc.id).expect("Error loading balances");
+
+        for d in reports::reconcile(acc.id, &checkpoints, &flows).into_iter() {
+            table.add_row(row![
+                acc.name,
+                uname,
+                d.from.format("%Y-%m-%d"),
+                d.to.format("%Y-%m-%d"),
+                r->format_amount(d.balance_change),
+                r->format_amount(d.transaction_sum),
+                r->format_amount(d.difference)
+            ]);
+            count += 1;
+        }
+    }
+
+    if count > 0 {
+        table.printstd();
+    }
+    info!("found {} intervals with discrepancies", count);
+}
@@ -1,6 +1,11 @@
+pub mod check_balances;
 pub mod export;
 pub mod import;
+pub mod recurrences;
 pub mod serve;
+pub mod tax;
+pub mod template;
+pub mod trash;
 pub mod user;
 
 use clap::{crate_authors, crate_name, crate_version, App, Arg};
@@ -33,6 +38,11 @@ pub fn build(multiple: bool) -> App<'static, 'static> {
         .subcommand(import::build())
         .subcommand(export::build())
         .subcommand(serve::build())
+        .subcommand(trash::build())
+        .subcommand(template::build())
+        .subcommand(check_balances::build())
+        .subcommand(recurrences::build())
+        .subcommand(tax::build())
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -41,7 +51,9 @@ pub struct Config {
     #[serde(skip)]
     pub verbosity: i64,
     pub database: String,
+    pub receipts: String, // directory that uploaded receipts are stored in
     pub web: serve::Config,
+    pub trash: trash::Config,
 }
 
 impl Default for Config {
@@ -49,7 +61,9 @@ impl Default for Config {
         Self {
             database: "postgres://localhost/moneta".into(),
             verbosity: 0,
+            receipts: "receipts".into(),
             web: Default::default(),
+            trash: Default::default(),
         }
     }
 }
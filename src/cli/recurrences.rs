@@ -0,0 +1,65 @@
+use crate::models::*;
+use crate::recurrences;
+use crate::reports::format_amount;
+use crate::schema::*;
+
+use clap::ArgMatches;
+use clap::{App, Arg, SubCommand};
+use diesel::prelude::*;
+use log::info;
+use prettytable::{cell, row, Table};
+
+pub fn build() -> App<'static, 'static> {
+    SubCommand::with_name("recurrences")
+        .about("Find expenses that recur regularly, e.g. forgotten subscriptions")
+        .arg(
+            Arg::with_name("user")
+                .long("user")
+                .short("u")
+                .value_name("name")
+                .required(true)
+                .help("user whose expenses are analyzed"),
+        )
+}
+
+pub fn handle(connection: &PgConnection, sub_matches: &ArgMatches<'_>) {
+    let uname = sub_matches.value_of("user").unwrap();
+    let user = users::table
+        .filter(users::name.eq(uname))
+        .get_result::<User>(connection)
+        .optional()
+        .expect("Error loading users")
+        .unwrap_or_else(|| panic!("there is no user with name '{}'!", uname));
+
+    let occurrences =
+        recurrences::occurrences(connection, user.id).expect("Error loading expenses");
+    let recs = recurrences::detect(&occurrences);
+
+    let mut table = Table::new();
+    table.add_row(row![
+        "Store/Statement",
+        "Account",
+        "Interval",
+        "Amount",
+        "Count",
+        "Last",
+        "Next"
+    ]);
+
+    for r in recs.iter() {
+        table.add_row(row![
+            r.key,
+            r.account_id,
+            format!("{:?}", r.interval).to_lowercase(),
+            r->format_amount(r.amount),
+            r->r.expense_ids.len(),
+            r.last.format("%Y-%m-%d"),
+            r.next.format("%Y-%m-%d")
+        ]);
+    }
+
+    if !recs.is_empty() {
+        table.printstd();
+    }
+    info!("found {} recurring expenses", recs.len());
+}
@@ -35,6 +35,7 @@ pub struct Config {
     pub secret_key: String,
     pub port: i64,
     pub address: String,
+    pub forecast_threshold: i64, // cents, see `web::reports::forecast`
 }
 
 impl Default for Config {
@@ -43,6 +44,7 @@ impl Default for Config {
             secret_key: String::new(),
             port: 8484,
             address: "127.0.0.1".into(),
+            forecast_threshold: 0,
         }
     }
 }
@@ -51,6 +53,7 @@ pub async fn handle(
     sub_matches: &ArgMatches<'_>,
     mut config: Config,
     database: String,
+    receipts: String,
     verbosity: i64,
 ) {
     if let Some(y) = sub_matches.value_of("port") {
@@ -81,6 +84,8 @@ pub async fn handle(
         },
         log_level: level,
         database_url: database,
+        receipts,
+        forecast_threshold: config.forecast_threshold,
     })
     .await;
 }
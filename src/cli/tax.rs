@@ -0,0 +1,78 @@
+use crate::models::*;
+use crate::schema::*;
+use crate::tax;
+
+use clap::ArgMatches;
+use clap::{App, Arg, SubCommand};
+use diesel::prelude::*;
+use log::info;
+use std::fs;
+use std::io::{self, Write};
+
+pub fn build() -> App<'static, 'static> {
+    SubCommand::with_name("tax")
+        .about("Report of the tax relevant expenses of a year")
+        .arg(
+            Arg::with_name("year")
+                .value_name("year")
+                .required(true)
+                .help("year of the report, e.g. '2020'"),
+        )
+        .arg(
+            Arg::with_name("user")
+                .long("user")
+                .short("u")
+                .value_name("name")
+                .required(true)
+                .help("user whose expenses are reported"),
+        )
+        .arg(
+            Arg::with_name("format")
+                .long("format")
+                .value_name("format")
+                .possible_values(&["csv", "pdf", "json"])
+                .default_value("csv"),
+        )
+        .arg(
+            Arg::with_name("output")
+                .long("output")
+                .short("o")
+                .value_name("file")
+                .help("write the report to this file [default: stdout]"),
+        )
+}
+
+pub fn handle(connection: &PgConnection, sub_matches: &ArgMatches<'_>) {
+    let year: i32 = sub_matches
+        .value_of("year")
+        .unwrap()
+        .parse()
+        .expect("cannot parse year");
+
+    let uname = sub_matches.value_of("user").unwrap();
+    let user = users::table
+        .filter(users::name.eq(uname))
+        .get_result::<User>(connection)
+        .optional()
+        .expect("Error loading users")
+        .unwrap_or_else(|| panic!("there is no user with name '{}'!", uname));
+
+    let report = tax::tax_report(connection, user.id, year)
+        .expect("Error loading expenses")
+        .unwrap_or_else(|| panic!("{} is not a valid year!", year));
+    let data = match sub_matches.value_of("format").unwrap() {
+        "pdf" => tax::to_pdf(&report).expect("Unable to create pdf"),
+        "json" => serde_json::to_vec(&report).expect("Could not serialize data"),
+        _ => tax::to_csv(&report).expect("Unable to create csv"),
+    };
+
+    match sub_matches.value_of("output") {
+        Some(file) => {
+            fs::write(file, data).expect("Unable to write report");
+            info!("wrote tax report {} of user '{}' to {}", year, uname, file);
+        }
+        None => io::stdout()
+            .write_all(&data)
+            .expect("Unable to write report"),
+    }
+}
@@ -0,0 +1,101 @@
+use crate::models::*;
+use crate::mutations;
+use crate::queries;
+use crate::schema::*;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use clap::ArgMatches;
+use clap::{App, Arg, SubCommand};
+use diesel::prelude::*;
+use log::info;
+
+pub fn build() -> App<'static, 'static> {
+    SubCommand::with_name("template")
+        .about("Create expenses from templates")
+        .arg(
+            Arg::with_name("id")
+                .value_name("id")
+                .required(true)
+                .help("id of the template"),
+        )
+        .arg(
+            Arg::with_name("user")
+                .long("user")
+                .short("u")
+                .value_name("name")
+                .required(true)
+                .help("user that creates the expense"),
+        )
+        .arg(
+            Arg::with_name("date")
+                .long("date")
+                .value_name("date")
+                .help("date of the new expense, e.g. '2021-02-15' [default: now]"),
+        )
+        .arg(
+            Arg::with_name("amount")
+                .long("amount")
+                .value_name("amount")
+                .allow_hyphen_values(true)
+                .help("replaces the fixed amount of the template, e.g. '-12.34'"),
+        )
+}
+
+pub fn handle(connection: &PgConnection, sub_matches: &ArgMatches<'_>) {
+    let id: i32 = sub_matches
+        .value_of("id")
+        .unwrap()
+        .parse()
+        .expect("cannot parse id");
+
+    let uname = sub_matches.value_of("user").unwrap();
+    let user = users::table
+        .filter(users::name.eq(uname))
+        .get_result::<User>(connection)
+        .optional()
+        .expect("Error loading users")
+        .unwrap_or_else(|| panic!("there is no user with name '{}'!", uname));
+
+    let date = match sub_matches.value_of("date") {
+        Some(d) => DateTime::<Utc>::from_utc(
+            NaiveDate::parse_from_str(d, "%Y-%m-%d")
+                .expect("cannot parse date")
+                .and_hms(0, 0, 0),
+            Utc,
+        ),
+        None => Utc::now(),
+    };
+
+    let amount = sub_matches.value_of("amount").map(|a| {
+        (a.parse::<f64>().expect("cannot parse amount") * 100.0).round() as i64 // cents
+    });
+
+    let template = queries::relevant_expense_by_id(connection, user.id, id)
+        .expect("Error loading expenses")
+        .unwrap_or_else(|| panic!("user '{}' has no expense with id {}!", uname, id));
+    assert!(
+        template.is_template && !template.is_deleted,
+        "expense {} is not a template!",
+        id
+    );
+
+    if amount.is_some() {
+        let num_amounts = expense_transactions::table
+            .filter(expense_transactions::expense_id.eq(id))
+            .filter(expense_transactions::amount.is_not_null())
+            .select(diesel::dsl::count(expense_transactions::id))
+            .get_result::<i64>(connection)
+            .expect("Error loading transactions");
+        assert!(
+            num_amounts == 1,
+            "the amount of template {} cannot be replaced because it has {} fixed amounts!",
+            id,
+            num_amounts
+        );
+    }
+
+    let exp = mutations::instantiate_template(connection, user.id, id, date, amount)
+        .expect("Error creating expense");
+
+    info!("Created expense {} from template {}", exp.id, id);
+}
@@ -0,0 +1,95 @@
+use crate::mutations;
+use crate::receipts::ReceiptStore;
+use crate::schema::*;
+
+use chrono::{DateTime, Duration, Utc};
+use clap::ArgMatches;
+use clap::{App, Arg, ArgGroup, SubCommand};
+use diesel::prelude::*;
+use log::info;
+use prettytable::{cell, row, Table};
+use serde::{Deserialize, Serialize};
+
+pub fn build() -> App<'static, 'static> {
+    SubCommand::with_name("trash")
+        .about("Management of deleted expenses")
+        .arg(
+            Arg::with_name("list")
+                .long("list")
+                .help("list deleted expenses"),
+        )
+        .arg(
+            Arg::with_name("purge")
+                .long("purge")
+                .help("permanently remove expenses that have been deleted for a while"),
+        )
+        .arg(
+            Arg::with_name("age").long("age").value_name("days").help(
+                "minimum age of deleted expenses that are purged [default: taken from config]",
+            ),
+        )
+        .group(
+            ArgGroup::with_name("action")
+                .args(&["list", "purge"])
+                .required(true),
+        )
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(default)]
+pub struct Config {
+    pub max_age: i64, // days
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self { max_age: 30 }
+    }
+}
+
+pub fn handle(
+    connection: &PgConnection,
+    sub_matches: &ArgMatches<'_>,
+    config: Config,
+    receipts: &str,
+) {
+    if sub_matches.is_present("list") {
+        let exps = expenses::table
+            .filter(expenses::is_deleted.eq(true))
+            .select((expenses::id, expenses::title, expenses::booking_start))
+            .order(expenses::id)
+            .load::<(i32, String, DateTime<Utc>)>(connection)
+            .expect("Error loading expenses");
+
+        let mut table = Table::new();
+        table.add_row(row!["ID", "Title", "Date"]);
+
+        for (id, title, date) in exps.into_iter() {
+            table.add_row(row![id, title, date.format("%Y-%m-%d")]);
+        }
+
+        table.printstd();
+    } else if sub_matches.is_present("purge") {
+        let max_age = sub_matches
+            .value_of("age")
+            .map(|a| a.parse().expect("cannot parse age"))
+            .unwrap_or(config.max_age);
+
+        let ids =
+            mutations::purge_deleted_expenses(connection, Utc::now() - Duration::days(max_age))
+                .expect("Unable to purge deleted expenses");
+
+        info!(
+            "permanently removed {} expenses that were deleted more than {} days ago",
+            ids.len(),
+            max_age
+        );
+
+        // receipts of purged expenses are not needed anymore, unless other expenses use the same files
+        ReceiptStore::new(receipts)
+            .remove_unreferenced(connection)
+            .expect("Unable to remove unreferenced receipts");
+    } else {
+        panic!("unexpected options for subcommand 'trash'");
+    }
+}
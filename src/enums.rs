@@ -1,7 +1,9 @@
 use diesel_derive_enum::DbEnum;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, DbEnum)]
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, DbEnum,
+)]
 #[DieselType = "Account_availability"]
 pub enum AccountAvailability {
     Immediately,
@@ -12,7 +14,9 @@ pub enum AccountAvailability {
     Decades,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, DbEnum)]
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, DbEnum,
+)]
 #[DieselType = "Account_risk"]
 pub enum AccountRisk {
     None,
@@ -23,7 +27,9 @@ pub enum AccountRisk {
     Huge,
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize, DbEnum)]
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, DbEnum,
+)]
 #[DieselType = "Account_kind"]
 pub enum AccountKind {
     Cash,
@@ -35,6 +41,14 @@ pub enum AccountKind {
     Other,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, DbEnum)]
+#[DieselType = "Budget_period"]
+pub enum BudgetPeriod {
+    Month,
+    Quarter,
+    Year,
+}
+
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, DbEnum)]
 #[DieselType = "Expense_event_type"]
 pub enum ExpenseEventType {
@@ -50,4 +64,5 @@ pub enum ExpenseEventTarget {
     Categories,
     Receipts,
     Transactions,
+    Contents, // multiple targets at once, see `mutations::update_expenses`
 }
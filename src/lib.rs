@@ -1,10 +1,15 @@
 pub mod cli;
 pub mod enums;
 pub mod models;
+pub mod mutations;
 pub mod queries;
+pub mod receipts;
+pub mod recurrences;
 pub mod rendering;
+pub mod reports;
 pub mod schema;
 pub mod serialization;
+pub mod tax;
 pub mod web;
 
 #[macro_use]
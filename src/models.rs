@@ -124,6 +124,29 @@ pub struct AccountSynchronization {
     pub user2: i32, // owner of account2
     pub invert: bool,
 }
+
+#[derive(Debug, Clone, Queryable, Identifiable, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+#[table_name = "account_sync_invitations"]
+pub struct AccountSyncInvitation {
+    // proposal of `inviter` to synchronize their `account` with `other_account` of `invitee`
+    pub id: i32,
+    pub account: i32,
+    pub other_account: i32,
+    pub inviter: i32,
+    pub invitee: i32,
+}
+
+#[derive(Debug, Clone, Insertable, Serialize, Deserialize)]
+#[table_name = "account_sync_invitations"]
+#[serde(rename_all = "camelCase")]
+pub struct NewAccountSyncInvitation {
+    pub account: i32,
+    pub other_account: i32,
+    pub inviter: i32,
+    pub invitee: i32,
+}
+
 #[derive(
     Debug,
     Clone,
@@ -398,6 +421,34 @@ pub struct NewDeliveryRule {
     pub last_match: Option<DateTime<Utc>>,
 }
 
+#[derive(
+    Debug, Clone, Queryable, Associations, Identifiable, Serialize, Deserialize, AsChangeset,
+)]
+#[belongs_to(Category, foreign_key = "category_id")]
+#[belongs_to(User, foreign_key = "user_id")]
+#[serde(rename_all = "camelCase")]
+pub struct Budget {
+    pub id: i32,
+    pub user_id: i32,
+    pub category_id: i32,
+    pub period: BudgetPeriod,
+    pub amount: i64, // limit of the spending per period, including all subcategories
+    pub rollover: bool, // whether unspent amounts are added to the next period
+    pub start: DateTime<Utc>, // rollover is accumulated starting with the period that contains this date
+}
+
+#[derive(Debug, Clone, Insertable, Serialize, Deserialize)]
+#[table_name = "budgets"]
+#[serde(rename_all = "camelCase")]
+pub struct NewBudget {
+    pub user_id: i32,
+    pub category_id: i32,
+    pub period: BudgetPeriod,
+    pub amount: i64,
+    pub rollover: bool,
+    pub start: DateTime<Utc>,
+}
+
 // TODO: Expense Delivery by API
 // SeenExpense json sql=seen_expenses
 //     valueDate     UTCTime
@@ -0,0 +1,851 @@
+use crate::enums::*;
+use crate::models::*;
+use crate::queries;
+use crate::schema::*;
+
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::BTreeSet;
+
+pub fn log_expense_event(
+    connection: &PgConnection,
+    user_id: i32,
+    expense_id: i32,
+    tool: &str,
+    event_type: ExpenseEventType,
+    event_target: ExpenseEventTarget,
+    payload: Option<String>,
+) -> Result<ExpenseEvent, diesel::result::Error> {
+    diesel::insert_into(expense_events::table)
+        .values(&NewExpenseEvent {
+            expense_id,
+            user_id,
+            date: Utc::now(),
+            tool: tool.to_string(),
+            automatic: false,
+            event_type,
+            event_target,
+            payload,
+        })
+        .get_result::<ExpenseEvent>(connection)
+}
+
+// inserts an expense together with its transactions and categories (their expense ids are overwritten) and logs its creation.
+// does not check any constraints, the caller is responsible for that.
+pub fn insert_expense(
+    connection: &PgConnection,
+    user_id: i32,
+    tool: &str,
+    info: &NewExpense,
+    transactions: Vec<NewExpenseTransaction>,
+    categories: Vec<ExpenseCategory>,
+) -> Result<Expense, diesel::result::Error> {
+    connection.transaction::<_, diesel::result::Error, _>(|| {
+        let expense = diesel::insert_into(expenses::table)
+            .values(info)
+            .get_result::<Expense>(connection)?;
+
+        let transactions = transactions
+            .into_iter()
+            .map(|mut t| {
+                t.expense_id = expense.id;
+                t
+            })
+            .collect::<Vec<_>>();
+        diesel::insert_into(expense_transactions::table)
+            .values(&transactions)
+            .execute(connection)?;
+
+        let categories = categories
+            .into_iter()
+            .map(|mut c| {
+                c.expense_id = expense.id;
+                c
+            })
+            .collect::<Vec<_>>();
+        diesel::insert_into(expense_categories::table)
+            .values(&categories)
+            .execute(connection)?;
+
+        log_expense_event(
+            connection,
+            user_id,
+            expense.id,
+            tool,
+            ExpenseEventType::Create,
+            ExpenseEventTarget::Expense,
+            None,
+        )?;
+
+        Ok(expense)
+    })
+}
+
+// creates a concrete expense from the template `id`: the booking period is moved to start at `date` and all transactions
+// and categories are copied (transactions get `date` as well). `amount` is given as seen by `user_id` and replaces the
+// amount of the only transaction with a fixed amount, the caller has to check that there is exactly one.
+pub fn instantiate_template(
+    connection: &PgConnection,
+    user_id: i32,
+    id: i32,
+    date: DateTime<Utc>,
+    amount: Option<i64>,
+) -> Result<Expense, diesel::result::Error> {
+    let template = load_expense_contents(connection, id)?;
+    let rendered_transactions =
+        queries::expense_transactions_by_expense_id(connection, user_id, id)?;
+
+    let info = NewExpense {
+        title: template.info.title.clone(),
+        description: template.info.description.clone(),
+        store: template.info.store.clone(),
+        comments: template.info.comments.clone(),
+        booking_start: date,
+        booking_end: date + (template.info.booking_end - template.info.booking_start),
+        is_deleted: false,
+        is_template: false,
+        is_preliminary: template.info.is_preliminary,
+        is_tax_relevant: template.info.is_tax_relevant,
+        is_unchecked: template.info.is_unchecked,
+    };
+
+    let transactions = template
+        .transactions
+        .iter()
+        .map(|t| {
+            // undo the inversion of `RenderedExpense::render` for transactions on synchronized accounts
+            let invert = rendered_transactions
+                .iter()
+                .any(|(st, _, acs)| st.id == t.id && acs.as_ref().map_or(false, |s| s.invert));
+
+            NewExpenseTransaction {
+                expense_id: 0,
+                account_id: t.account_id,
+                date,
+                amount: match (t.amount, amount) {
+                    (Some(_), Some(y)) if invert => Some(-y),
+                    (Some(_), Some(y)) => Some(y),
+                    (x, _) => x,
+                },
+                fraction: t.fraction,
+                comments: t.comments.clone(),
+                statement: t.statement.clone(),
+            }
+        })
+        .collect();
+
+    insert_expense(
+        connection,
+        user_id,
+        &format!("template:{}", id),
+        &info,
+        transactions,
+        template.categories,
+    )
+}
+
+// the opposite of `instantiate_template`: copies the expense `id` (with its transactions and categories) as a template
+pub fn create_template(
+    connection: &PgConnection,
+    user_id: i32,
+    tool: &str,
+    id: i32,
+) -> Result<Expense, diesel::result::Error> {
+    let expense = load_expense_contents(connection, id)?;
+
+    let info = NewExpense {
+        title: expense.info.title.clone(),
+        description: expense.info.description.clone(),
+        store: expense.info.store.clone(),
+        comments: expense.info.comments.clone(),
+        booking_start: expense.info.booking_start,
+        booking_end: expense.info.booking_end,
+        is_deleted: false,
+        is_template: true,
+        is_preliminary: expense.info.is_preliminary,
+        is_tax_relevant: expense.info.is_tax_relevant,
+        is_unchecked: expense.info.is_unchecked,
+    };
+
+    let transactions = expense
+        .transactions
+        .into_iter()
+        .map(|t| NewExpenseTransaction {
+            expense_id: 0,
+            account_id: t.account_id,
+            date: t.date,
+            amount: t.amount,
+            fraction: t.fraction,
+            comments: t.comments,
+            statement: t.statement,
+        })
+        .collect();
+
+    insert_expense(
+        connection,
+        user_id,
+        tool,
+        &info,
+        transactions,
+        expense.categories,
+    )
+}
+
+// books `amount` (as seen by `user_id`, who has to own the accounts) out of the synchronized `account_id`, so that the
+// balance between the user and their partner is reduced by it. If the money was transferred through another account,
+// it is booked on `payment_account_id` in the same expense, which therefore has a total amount of 0.
+pub fn record_settlement(
+    connection: &PgConnection,
+    user_id: i32,
+    tool: &str,
+    account_id: i32,
+    payment_account_id: Option<i32>,
+    amount: i64,
+    date: DateTime<Utc>,
+) -> Result<Expense, diesel::result::Error> {
+    let partner = match queries::synchronization_by_account_id(connection, account_id)? {
+        Some(sync) if sync.user1 == user_id => sync.user2,
+        Some(sync) => sync.user1,
+        None => return Err(diesel::result::Error::NotFound),
+    };
+    let partner_name = users::table
+        .find(partner)
+        .select(users::full_name)
+        .get_result::<String>(connection)?;
+
+    let info = NewExpense {
+        title: format!("Settlement with {}", partner_name),
+        description: String::new(),
+        store: String::new(),
+        comments: String::new(),
+        booking_start: date,
+        booking_end: date,
+        is_deleted: false,
+        is_template: false,
+        is_preliminary: false,
+        is_tax_relevant: false,
+        is_unchecked: false,
+    };
+
+    let transaction = |account_id, amount| NewExpenseTransaction {
+        expense_id: 0,
+        account_id,
+        date,
+        amount: Some(amount),
+        fraction: None,
+        comments: String::new(),
+        statement: String::new(),
+    };
+    let mut transactions = vec![transaction(account_id, -amount)];
+    if let Some(payment_account_id) = payment_account_id {
+        transactions.push(transaction(payment_account_id, amount));
+    }
+
+    insert_expense(connection, user_id, tool, &info, transactions, Vec::new())
+}
+
+// moves an expense to the trash (logging a `Delete` event) or restores it from there (logging a `Modify` event).
+// returns `None` if the expense already was in the requested state.
+pub fn set_expense_deleted(
+    connection: &PgConnection,
+    user_id: i32,
+    tool: &str,
+    id: i32,
+    is_deleted: bool,
+) -> Result<Option<ExpenseEvent>, diesel::result::Error> {
+    connection.transaction::<_, diesel::result::Error, _>(|| {
+        let old = expenses::table.find(id).get_result::<Expense>(connection)?;
+        if old.is_deleted == is_deleted {
+            return Ok(None);
+        }
+
+        let new = diesel::update(&old)
+            .set(expenses::is_deleted.eq(is_deleted))
+            .get_result::<Expense>(connection)?;
+        let diff = ExpenseDiff::new(json!(old), json!(new))
+            .map(|d| serde_json::to_string(&d).expect("unable to serialize diff"));
+
+        log_expense_event(
+            connection,
+            user_id,
+            id,
+            tool,
+            if is_deleted {
+                ExpenseEventType::Delete
+            } else {
+                ExpenseEventType::Modify
+            },
+            ExpenseEventTarget::Expense,
+            diff,
+        )
+        .map(Some)
+    })
+}
+
+// permanently removes all expenses that have been in the trash since before `cutoff`
+// (expenses without a `Delete` event are considered to be deleted since forever)
+pub fn purge_deleted_expenses(
+    connection: &PgConnection,
+    cutoff: DateTime<Utc>,
+) -> Result<Vec<i32>, diesel::result::Error> {
+    diesel::delete(
+        expenses::table
+            .filter(expenses::is_deleted.eq(true))
+            .filter(diesel::dsl::not(
+                expenses::id.eq_any(
+                    expense_events::table
+                        .select(expense_events::expense_id)
+                        .filter(expense_events::event_type.eq(ExpenseEventType::Delete))
+                        .filter(expense_events::date.ge(cutoff)),
+                ),
+            )),
+    )
+    .returning(expenses::id)
+    .get_results::<i32>(connection)
+}
+
+// everything that belongs to an expense in the form in which it is stored (i.e. not rendered for a specific user)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExpenseContents {
+    pub info: Expense,
+    pub transactions: Vec<ExpenseTransaction>,
+    pub categories: Vec<ExpenseCategory>,
+    pub receipts: Vec<ExpenseReceipt>,
+}
+
+// payload of `Modify` events: the old and new values of the event's target.
+// objects only contain the fields that differ, lists are always stored as a whole.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExpenseDiff {
+    pub old: Value,
+    pub new: Value,
+}
+
+impl ExpenseDiff {
+    pub fn new(old: Value, new: Value) -> Option<ExpenseDiff> {
+        if old == new {
+            return None;
+        }
+
+        match (old, new) {
+            (Value::Object(old), Value::Object(new)) => {
+                let keys = old
+                    .keys()
+                    .chain(new.keys())
+                    .cloned()
+                    .collect::<BTreeSet<_>>();
+                let mut old_diff = Map::new();
+                let mut new_diff = Map::new();
+
+                for k in keys.into_iter() {
+                    let o = old.get(&k);
+                    let n = new.get(&k);
+                    if o != n {
+                        if let Some(o) = o {
+                            old_diff.insert(k.clone(), o.clone());
+                        }
+                        if let Some(n) = n {
+                            new_diff.insert(k, n.clone());
+                        }
+                    }
+                }
+
+                Some(ExpenseDiff {
+                    old: Value::Object(old_diff),
+                    new: Value::Object(new_diff),
+                })
+            }
+            (old, new) => Some(ExpenseDiff { old, new }),
+        }
+    }
+
+    // applies the diff backwards, i.e. turns the new value of its target into the old one
+    pub fn revert(&self, value: &mut Value) {
+        match (&self.old, &self.new, value) {
+            (Value::Object(old), Value::Object(new), Value::Object(value)) => {
+                for k in new.keys().filter(|k| !old.contains_key(*k)) {
+                    value.remove(k);
+                }
+                for (k, v) in old.iter() {
+                    value.insert(k.clone(), v.clone());
+                }
+            }
+            (old, _, value) => *value = old.clone(),
+        }
+    }
+}
+
+// state of an expense right after one of its events.
+// `contents` is `None` if it cannot be rebuilt, e.g. because a later event has no usable payload.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExpenseVersion {
+    pub event: ExpenseEvent,
+    pub contents: Option<ExpenseContents>,
+}
+
+// all versions of an expense (oldest first), rebuilt by applying the diffs of its events backwards to the current state
+pub fn expense_history(
+    connection: &PgConnection,
+    id: i32,
+) -> Result<Vec<ExpenseVersion>, diesel::result::Error> {
+    let current = load_expense_contents(connection, id)?;
+    let events = expense_events::table
+        .filter(expense_events::expense_id.eq(id))
+        .order(expense_events::id.desc())
+        .load::<ExpenseEvent>(connection)?;
+
+    let mut state = Some(json!(current));
+    let mut versions = Vec::new();
+    for event in events.into_iter() {
+        let contents = state
+            .clone()
+            .and_then(|s| serde_json::from_value::<ExpenseContents>(s).ok());
+
+        // go back to the state before this event
+        state = match (state, event.event_type, &event.payload) {
+            (Some(mut s), ExpenseEventType::Modify, Some(payload))
+            | (Some(mut s), ExpenseEventType::Delete, Some(payload)) => {
+                serde_json::from_str::<ExpenseDiff>(payload)
+                    .ok()
+                    .map(|diff| {
+                        match event.event_target {
+                            ExpenseEventTarget::Expense => diff.revert(&mut s["info"]),
+                            ExpenseEventTarget::Transactions => diff.revert(&mut s["transactions"]),
+                            ExpenseEventTarget::Categories => diff.revert(&mut s["categories"]),
+                            ExpenseEventTarget::Receipts => diff.revert(&mut s["receipts"]),
+                            ExpenseEventTarget::Contents => diff.revert(&mut s),
+                        };
+                        s
+                    })
+            }
+            _ => None,
+        };
+
+        versions.push(ExpenseVersion { event, contents });
+    }
+
+    versions.reverse();
+    Ok(versions)
+}
+
+pub fn load_expense_contents(
+    connection: &PgConnection,
+    id: i32,
+) -> Result<ExpenseContents, diesel::result::Error> {
+    let info = expenses::table.find(id).get_result::<Expense>(connection)?;
+    let transactions = expense_transactions::table
+        .filter(expense_transactions::expense_id.eq(id))
+        .order(expense_transactions::id.asc())
+        .load::<ExpenseTransaction>(connection)?;
+    let categories = expense_categories::table
+        .filter(expense_categories::expense_id.eq(id))
+        .order(expense_categories::category_id.asc())
+        .load::<ExpenseCategory>(connection)?;
+    let receipts = expense_receipts::table
+        .filter(expense_receipts::expense_id.eq(id))
+        .order(expense_receipts::id.asc())
+        .load::<ExpenseReceipt>(connection)?;
+
+    Ok(ExpenseContents {
+        info,
+        transactions,
+        categories,
+        receipts,
+    })
+}
+
+// replaces the stored contents of the expense `contents.info.id` and logs one `Modify` event for each target that changed.
+// transactions and receipts whose id does not belong to the expense (e.g. 0) are inserted as new rows.
+// does not check any constraints, the caller is responsible for that.
+pub fn update_expense(
+    connection: &PgConnection,
+    user_id: i32,
+    tool: &str,
+    contents: ExpenseContents,
+) -> Result<Vec<ExpenseEvent>, diesel::result::Error> {
+    connection.transaction::<_, diesel::result::Error, _>(|| {
+        let (old, new) = replace_expense_contents(connection, contents)?;
+        log_modifications(connection, user_id, tool, &old, &new)
+    })
+}
+
+// replaces the stored contents of many expenses at once (in a single transaction) and logs one `Modify` event
+// with target `Contents` for each expense that changed. See `update_expense` for details.
+pub fn update_expenses(
+    connection: &PgConnection,
+    user_id: i32,
+    tool: &str,
+    contents: Vec<ExpenseContents>,
+) -> Result<Vec<ExpenseEvent>, diesel::result::Error> {
+    connection.transaction::<_, diesel::result::Error, _>(|| {
+        let mut events = Vec::new();
+        for c in contents.into_iter() {
+            let (old, new) = replace_expense_contents(connection, c)?;
+
+            if let Some(diff) = ExpenseDiff::new(json!(old), json!(new)) {
+                events.push(log_expense_event(
+                    connection,
+                    user_id,
+                    new.info.id,
+                    tool,
+                    ExpenseEventType::Modify,
+                    ExpenseEventTarget::Contents,
+                    Some(serde_json::to_string(&diff).expect("unable to serialize diff")),
+                )?);
+            }
+        }
+
+        Ok(events)
+    })
+}
+
+// returns the old and new contents, callers have to take care of the transaction and the events
+fn replace_expense_contents(
+    connection: &PgConnection,
+    contents: ExpenseContents,
+) -> Result<(ExpenseContents, ExpenseContents), diesel::result::Error> {
+    let id = contents.info.id;
+    let old = load_expense_contents(connection, id)?;
+
+    diesel::update(&contents.info)
+        .set(&contents.info)
+        .execute(connection)?;
+
+    diesel::delete(
+        expense_transactions::table
+            .filter(expense_transactions::expense_id.eq(id))
+            .filter(
+                expense_transactions::id.ne_all(
+                    contents
+                        .transactions
+                        .iter()
+                        .map(|t| t.id)
+                        .collect::<Vec<_>>(),
+                ),
+            ),
+    )
+    .execute(connection)?;
+
+    let old_ids = old.transactions.iter().map(|t| t.id).collect::<Vec<_>>();
+    for t in contents.transactions.iter() {
+        if old_ids.contains(&t.id) {
+            diesel::update(t).set(t).execute(connection)?;
+        } else {
+            diesel::insert_into(expense_transactions::table)
+                .values(&NewExpenseTransaction {
+                    expense_id: id,
+                    account_id: t.account_id,
+                    date: t.date,
+                    amount: t.amount,
+                    fraction: t.fraction,
+                    comments: t.comments.clone(),
+                    statement: t.statement.clone(),
+                })
+                .execute(connection)?;
+        }
+    }
+
+    diesel::delete(expense_categories::table.filter(expense_categories::expense_id.eq(id)))
+        .execute(connection)?;
+    diesel::insert_into(expense_categories::table)
+        .values(
+            contents
+                .categories
+                .iter()
+                .map(|c| ExpenseCategory {
+                    expense_id: id,
+                    category_id: c.category_id,
+                    weight: c.weight,
+                })
+                .collect::<Vec<_>>(),
+        )
+        .execute(connection)?;
+
+    diesel::delete(
+        expense_receipts::table
+            .filter(expense_receipts::expense_id.eq(id))
+            .filter(
+                expense_receipts::id
+                    .ne_all(contents.receipts.iter().map(|r| r.id).collect::<Vec<_>>()),
+            ),
+    )
+    .execute(connection)?;
+
+    let old_ids = old.receipts.iter().map(|r| r.id).collect::<Vec<_>>();
+    for r in contents.receipts.iter() {
+        if old_ids.contains(&r.id) {
+            diesel::update(r).set(r).execute(connection)?;
+        } else {
+            diesel::insert_into(expense_receipts::table)
+                .values(&NewExpenseReceipt {
+                    expense_id: id,
+                    file_name: r.file_name.clone(),
+                })
+                .execute(connection)?;
+        }
+    }
+
+    let new = load_expense_contents(connection, id)?;
+    Ok((old, new))
+}
+
+// logs one `Modify` event for each target that differs between `old` and `new`
+pub fn log_modifications(
+    connection: &PgConnection,
+    user_id: i32,
+    tool: &str,
+    old: &ExpenseContents,
+    new: &ExpenseContents,
+) -> Result<Vec<ExpenseEvent>, diesel::result::Error> {
+    let diffs = vec![
+        (
+            ExpenseEventTarget::Expense,
+            ExpenseDiff::new(json!(old.info), json!(new.info)),
+        ),
+        (
+            ExpenseEventTarget::Transactions,
+            ExpenseDiff::new(json!(old.transactions), json!(new.transactions)),
+        ),
+        (
+            ExpenseEventTarget::Categories,
+            ExpenseDiff::new(json!(old.categories), json!(new.categories)),
+        ),
+        (
+            ExpenseEventTarget::Receipts,
+            ExpenseDiff::new(json!(old.receipts), json!(new.receipts)),
+        ),
+    ];
+
+    let mut events = Vec::new();
+    for (target, diff) in diffs.into_iter() {
+        if let Some(diff) = diff {
+            events.push(log_expense_event(
+                connection,
+                user_id,
+                new.info.id,
+                tool,
+                ExpenseEventType::Modify,
+                target,
+                Some(serde_json::to_string(&diff).expect("unable to serialize diff")),
+            )?);
+        }
+    }
+
+    Ok(events)
+}
+
+// removes an account of `user_id`. Its transactions are either removed together with it or moved to the account `reassign_to`
+// (together with balances and delivery rules). Expenses that are left without a transaction with a fixed amount are removed as well,
+// changes to all other expenses are logged.
+pub fn delete_account(
+    connection: &PgConnection,
+    user_id: i32,
+    tool: &str,
+    id: i32,
+    reassign_to: Option<i32>,
+) -> Result<(), diesel::result::Error> {
+    connection.transaction::<_, diesel::result::Error, _>(|| {
+        let expense_ids = expense_transactions::table
+            .filter(expense_transactions::account_id.eq(id))
+            .select(expense_transactions::expense_id)
+            .distinct()
+            .load::<i32>(connection)?;
+        let olds = expense_ids
+            .into_iter()
+            .map(|eid| load_expense_contents(connection, eid))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if let Some(target) = reassign_to {
+            diesel::update(
+                expense_transactions::table.filter(expense_transactions::account_id.eq(id)),
+            )
+            .set(expense_transactions::account_id.eq(target))
+            .execute(connection)?;
+            diesel::update(balances::table.filter(balances::account_id.eq(id)))
+                .set(balances::account_id.eq(target))
+                .execute(connection)?;
+            diesel::update(delivery_rules::table.filter(delivery_rules::account_id.eq(id)))
+                .set(delivery_rules::account_id.eq(target))
+                .execute(connection)?;
+        }
+
+        diesel::delete(accounts::table.find(id)).execute(connection)?;
+
+        for old in olds.iter() {
+            let new = load_expense_contents(connection, old.info.id)?;
+
+            if new.transactions.iter().any(|t| t.amount.is_some()) {
+                log_modifications(connection, user_id, tool, old, &new)?;
+            } else {
+                diesel::delete(expenses::table.find(old.info.id)).execute(connection)?;
+            }
+        }
+
+        Ok(())
+    })
+}
+
+// moves everything from category `source` to category `target` (of the same user) and removes `source` afterwards.
+// expenses that end up with both categories get the sum of both weights.
+pub fn merge_category(
+    connection: &PgConnection,
+    user_id: i32,
+    tool: &str,
+    source: i32,
+    target: i32,
+) -> Result<(), diesel::result::Error> {
+    connection.transaction::<_, diesel::result::Error, _>(|| {
+        let expense_ids = expense_categories::table
+            .filter(expense_categories::category_id.eq(source))
+            .select(expense_categories::expense_id)
+            .load::<i32>(connection)?;
+        let olds = expense_ids
+            .into_iter()
+            .map(|eid| load_expense_contents(connection, eid))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for old in olds.iter() {
+            let source_row = expense_categories::table
+                .filter(expense_categories::expense_id.eq(old.info.id))
+                .filter(expense_categories::category_id.eq(source));
+
+            if let Some(t) = old.categories.iter().find(|ec| ec.category_id == target) {
+                let s = old
+                    .categories
+                    .iter()
+                    .find(|ec| ec.category_id == source)
+                    .unwrap();
+
+                diesel::update(
+                    expense_categories::table
+                        .filter(expense_categories::expense_id.eq(old.info.id))
+                        .filter(expense_categories::category_id.eq(target)),
+                )
+                .set(expense_categories::weight.eq(t.weight + s.weight))
+                .execute(connection)?;
+                diesel::delete(source_row).execute(connection)?;
+            } else {
+                diesel::update(source_row)
+                    .set(expense_categories::category_id.eq(target))
+                    .execute(connection)?;
+            }
+        }
+
+        diesel::update(
+            category_replacements::table.filter(category_replacements::replacement.eq(source)),
+        )
+        .set(category_replacements::replacement.eq(target))
+        .execute(connection)?;
+
+        // other users that replaced `source` now have to replace `target`, unless they already do
+        let replacing_users = category_replacements::table
+            .filter(category_replacements::original.eq(target))
+            .select(category_replacements::user_id)
+            .load::<i32>(connection)?;
+        diesel::delete(
+            category_replacements::table
+                .filter(category_replacements::original.eq(source))
+                .filter(category_replacements::user_id.eq_any(replacing_users)),
+        )
+        .execute(connection)?;
+        diesel::update(
+            category_replacements::table.filter(category_replacements::original.eq(source)),
+        )
+        .set(category_replacements::original.eq(target))
+        .execute(connection)?;
+
+        diesel::update(categories::table.filter(categories::parent.eq(source)))
+            .set(categories::parent.eq(target))
+            .execute(connection)?;
+        diesel::delete(categories::table.find(source)).execute(connection)?;
+
+        for old in olds.iter() {
+            let new = load_expense_contents(connection, old.info.id)?;
+            log_modifications(connection, user_id, tool, old, &new)?;
+        }
+
+        Ok(())
+    })
+}
+
+// removes categories and logs the changes to the expenses that used them
+pub fn delete_categories(
+    connection: &PgConnection,
+    user_id: i32,
+    tool: &str,
+    ids: Vec<i32>,
+) -> Result<(), diesel::result::Error> {
+    connection.transaction::<_, diesel::result::Error, _>(|| {
+        let expense_ids = expense_categories::table
+            .filter(expense_categories::category_id.eq_any(ids.clone()))
+            .select(expense_categories::expense_id)
+            .distinct()
+            .load::<i32>(connection)?;
+        let olds = expense_ids
+            .into_iter()
+            .map(|eid| load_expense_contents(connection, eid))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        diesel::delete(categories::table.filter(categories::id.eq_any(ids))).execute(connection)?;
+
+        for old in olds.iter() {
+            let new = load_expense_contents(connection, old.info.id)?;
+            log_modifications(connection, user_id, tool, old, &new)?;
+        }
+
+        Ok(())
+    })
+}
+
+// attaches already stored receipt files to an expense and logs a `Modify` event for its receipts
+pub fn add_receipts(
+    connection: &PgConnection,
+    user_id: i32,
+    tool: &str,
+    expense_id: i32,
+    file_names: Vec<String>,
+) -> Result<Vec<ExpenseReceipt>, diesel::result::Error> {
+    connection.transaction::<_, diesel::result::Error, _>(|| {
+        let old = load_expense_contents(connection, expense_id)?;
+
+        diesel::insert_into(expense_receipts::table)
+            .values(
+                file_names
+                    .into_iter()
+                    .map(|file_name| NewExpenseReceipt {
+                        expense_id,
+                        file_name,
+                    })
+                    .collect::<Vec<_>>(),
+            )
+            .execute(connection)?;
+
+        let new = load_expense_contents(connection, expense_id)?;
+        log_modifications(connection, user_id, tool, &old, &new)?;
+
+        Ok(new.receipts)
+    })
+}
+
+// removes a receipt from its expense, the stored file is left alone
+pub fn delete_receipt(
+    connection: &PgConnection,
+    user_id: i32,
+    tool: &str,
+    receipt: &ExpenseReceipt,
+) -> Result<(), diesel::result::Error> {
+    connection.transaction::<_, diesel::result::Error, _>(|| {
+        let old = load_expense_contents(connection, receipt.expense_id)?;
+
+        diesel::delete(receipt).execute(connection)?;
+
+        let new = load_expense_contents(connection, receipt.expense_id)?;
+        log_modifications(connection, user_id, tool, &old, &new)?;
+
+        Ok(())
+    })
+}
@@ -1,7 +1,12 @@
 use crate::models::*;
+use crate::rendering::RenderedExpense;
 use crate::schema::*;
 
+use chrono::{DateTime, Utc};
+use diesel::dsl::sql;
+use diesel::expression::SqlLiteral;
 use diesel::prelude::*;
+use diesel::sql_types::BigInt;
 
 pub fn accounts(
     connection: &PgConnection,
@@ -35,6 +40,20 @@ pub fn account_by_id(
         .optional()
 }
 
+pub fn synchronization_by_account_id(
+    connection: &PgConnection,
+    account_id: i32,
+) -> Result<Option<AccountSynchronization>, diesel::result::Error> {
+    account_synchronizations::table
+        .filter(
+            account_synchronizations::account1
+                .eq(account_id)
+                .or(account_synchronizations::account2.eq(account_id)),
+        )
+        .get_result::<AccountSynchronization>(connection)
+        .optional()
+}
+
 pub fn relevant_balances(
     connection: &PgConnection,
     user_id: i32,
@@ -95,6 +114,17 @@ pub fn relevant_balance_by_id(
         .optional()
 }
 
+// the total amount of each expense as seen by the user, see the sql function `expense_total_amount`.
+// the user id is inlined instead of being bound as a parameter, so that postgres recognizes the expression in `ORDER BY`
+// as the one in the select list of `DISTINCT` queries.
+pub fn total_amount_sql(user_id: i32) -> SqlLiteral<BigInt> {
+    sql(&total_amount_expression(user_id))
+}
+
+pub fn total_amount_expression(user_id: i32) -> String {
+    format!("expense_total_amount(expenses.id, {})", user_id)
+}
+
 pub fn relevant_expenses(
     connection: &PgConnection,
     user_id: i32,
@@ -124,6 +154,77 @@ pub fn relevant_expenses(
         .load::<Expense>(connection)
 }
 
+// the user's expenses that are neither deleted nor templates and whose booking period or one of whose transactions
+// overlaps [from, to), the exact parts have to be picked by the caller
+pub fn booked_expenses(
+    connection: &PgConnection,
+    user_id: i32,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<Expense>, diesel::result::Error> {
+    expenses::table
+        .select(expenses::all_columns)
+        .distinct()
+        .left_join(
+            expense_transactions::table.on(expense_transactions::expense_id.eq(expenses::id)),
+        )
+        .left_join(
+            account_synchronizations::table.on(expense_transactions::account_id
+                .eq(account_synchronizations::account1)
+                .or(expense_transactions::account_id.eq(account_synchronizations::account2))),
+        )
+        .left_join(accounts::table.on(accounts::id.eq(expense_transactions::account_id)))
+        .filter(
+            accounts::user_id
+                .eq(user_id)
+                .or(account_synchronizations::user1.eq(user_id))
+                .or(account_synchronizations::user2.eq(user_id)),
+        )
+        .filter(expenses::is_deleted.eq(false))
+        .filter(expenses::is_template.eq(false))
+        .filter(
+            expenses::booking_end
+                .nullable()
+                .ge(from)
+                .and(expenses::booking_start.nullable().lt(to))
+                .or(expense_transactions::date
+                    .ge(from)
+                    .and(expense_transactions::date.lt(to))),
+        )
+        .load::<Expense>(connection)
+}
+
+pub fn deleted_expenses(
+    connection: &PgConnection,
+    user_id: i32,
+    offset: Option<i64>,
+    limit: Option<i64>,
+) -> Result<Vec<Expense>, diesel::result::Error> {
+    expenses::table
+        .select(expenses::all_columns)
+        .distinct()
+        .left_join(
+            expense_transactions::table.on(expense_transactions::expense_id.eq(expenses::id)),
+        )
+        .left_join(
+            account_synchronizations::table.on(expense_transactions::account_id
+                .eq(account_synchronizations::account1)
+                .or(expense_transactions::account_id.eq(account_synchronizations::account2))),
+        )
+        .left_join(accounts::table.on(accounts::id.eq(expense_transactions::account_id)))
+        .filter(
+            accounts::user_id
+                .eq(user_id)
+                .or(account_synchronizations::user1.eq(user_id))
+                .or(account_synchronizations::user2.eq(user_id)),
+        )
+        .filter(expenses::is_deleted.eq(true))
+        .order(expenses::id.desc())
+        .limit(limit.unwrap_or(i64::MAX))
+        .offset(offset.unwrap_or(0))
+        .load::<Expense>(connection)
+}
+
 pub fn relevant_expense_by_id(
     connection: &PgConnection,
     user_id: i32,
@@ -348,3 +449,209 @@ pub fn relevant_transactions(
         .distinct()
         .load::<ExpenseTransaction>(connection)
 }
+
+// transactions of booked expenses (neither deleted nor templates) on the user's accounts and on accounts synchronized
+// with them, to be rendered for the user. as in `render_expenses`, the synchronization is only joined if the account
+// belongs to the other user, and only synchronizations that the user takes part in are considered
+pub fn renderable_transactions(
+    connection: &PgConnection,
+    user_id: i32,
+) -> Result<Vec<(ExpenseTransaction, Option<AccountSynchronization>)>, diesel::result::Error> {
+    expense_transactions::table
+        .inner_join(expenses::table.on(expenses::id.eq(expense_transactions::expense_id)))
+        .inner_join(accounts::table.on(accounts::id.eq(expense_transactions::account_id)))
+        .left_join(
+            account_synchronizations::table.on(((account_synchronizations::account1
+                .eq(expense_transactions::account_id)
+                .and(account_synchronizations::user1.ne(user_id)))
+            .or(account_synchronizations::account2
+                .eq(expense_transactions::account_id)
+                .and(account_synchronizations::user2.ne(user_id))))
+            .and(
+                account_synchronizations::user1
+                    .eq(user_id)
+                    .or(account_synchronizations::user2.eq(user_id)),
+            )),
+        )
+        .filter(
+            accounts::user_id
+                .eq(user_id)
+                .or(account_synchronizations::account1.is_not_null()),
+        )
+        .filter(expenses::is_deleted.eq(false))
+        .filter(expenses::is_template.eq(false))
+        .select((
+            expense_transactions::all_columns,
+            account_synchronizations::all_columns.nullable(),
+        ))
+        .load::<(ExpenseTransaction, Option<AccountSynchronization>)>(connection)
+}
+
+// accounts that the user may book transactions on: their own ones and the ones synchronized with them
+pub fn writable_account_ids(
+    connection: &PgConnection,
+    user_id: i32,
+) -> Result<Vec<i32>, diesel::result::Error> {
+    let mut ids = accounts::table
+        .filter(accounts::user_id.eq(user_id))
+        .select(accounts::id)
+        .load::<i32>(connection)?;
+
+    let syncs = account_synchronizations::table
+        .filter(
+            account_synchronizations::user1
+                .eq(user_id)
+                .or(account_synchronizations::user2.eq(user_id)),
+        )
+        .load::<AccountSynchronization>(connection)?;
+
+    ids.extend(syncs.into_iter().flat_map(|s| vec![s.account1, s.account2]));
+    ids.sort_unstable();
+    ids.dedup();
+
+    Ok(ids)
+}
+
+// users that share at least one synchronized account with the user
+pub fn partner_ids(
+    connection: &PgConnection,
+    user_id: i32,
+) -> Result<Vec<i32>, diesel::result::Error> {
+    let syncs = account_synchronizations::table
+        .filter(
+            account_synchronizations::user1
+                .eq(user_id)
+                .or(account_synchronizations::user2.eq(user_id)),
+        )
+        .load::<AccountSynchronization>(connection)?;
+
+    let mut ids = syncs
+        .into_iter()
+        .map(|s| if s.user1 == user_id { s.user2 } else { s.user1 })
+        .filter(|id| *id != user_id)
+        .collect::<Vec<_>>();
+    ids.sort_unstable();
+    ids.dedup();
+
+    Ok(ids)
+}
+
+// renders multiple expenses at once (with much less queries than rendering each of them individually)
+pub fn render_expenses(
+    connection: &PgConnection,
+    user_id: i32,
+    expenses: Vec<Expense>,
+) -> Result<Vec<RenderedExpense>, diesel::result::Error> {
+    let exp_ids = expenses.iter().map(|e| e.id).collect::<Vec<_>>();
+
+    let transactions = expense_transactions::table
+        .inner_join(accounts::table.on(accounts::id.eq(expense_transactions::account_id)))
+        .left_join(
+            account_synchronizations::table.on((account_synchronizations::account1
+                .eq(expense_transactions::account_id)
+                .and(account_synchronizations::user1.ne(user_id)))
+            .or(account_synchronizations::account2
+                .eq(expense_transactions::account_id)
+                .and(account_synchronizations::user2.ne(user_id)))),
+        )
+        .filter(
+            expense_transactions::expense_id.eq_any(exp_ids.iter().cloned().collect::<Vec<_>>()),
+        )
+        .order(expense_transactions::expense_id.asc())
+        .load::<(ExpenseTransaction, Account, Option<AccountSynchronization>)>(connection)?;
+
+    let categories = expense_categories::table
+        .left_join(
+            category_replacements::table.on(category_replacements::original
+                .eq(expense_categories::category_id)
+                .and(category_replacements::user_id.eq(user_id))),
+        )
+        .filter(expense_categories::expense_id.eq_any(exp_ids.iter().cloned().collect::<Vec<_>>()))
+        .order(expense_categories::expense_id.asc())
+        .load::<(ExpenseCategory, Option<CategoryReplacement>)>(connection)?;
+
+    let receipts = expense_receipts::table
+        .filter(expense_receipts::expense_id.eq_any(exp_ids.iter().cloned().collect::<Vec<_>>()))
+        .order(expense_receipts::expense_id.asc())
+        .load::<ExpenseReceipt>(connection)?;
+    let events = expense_events::table
+        .filter(expense_events::expense_id.eq_any(exp_ids))
+        .order(expense_events::expense_id.asc())
+        .then_order_by(expense_events::id.asc())
+        .load::<ExpenseEvent>(connection)?;
+
+    Ok(expenses
+        .into_iter()
+        .map(|e| {
+            RenderedExpense::filter_and_render(
+                user_id,
+                e,
+                &transactions[..],
+                &categories,
+                &receipts,
+                &events,
+            )
+        })
+        .collect::<Vec<_>>())
+}
+
+// the amounts (with the dates of their transactions) that flowed into an account of `user_id`, as seen by them.
+// transactions on a synchronized account are included, deleted expenses and templates are not.
+pub fn account_flows(
+    connection: &PgConnection,
+    user_id: i32,
+    account_id: i32,
+) -> Result<Vec<(DateTime<Utc>, i64)>, diesel::result::Error> {
+    let mut ids = vec![account_id];
+    if let Some(sync) = synchronization_by_account_id(connection, account_id)? {
+        ids.push(sync.account1);
+        ids.push(sync.account2);
+    }
+
+    let exps = expenses::table
+        .inner_join(
+            expense_transactions::table.on(expense_transactions::expense_id.eq(expenses::id)),
+        )
+        .filter(expense_transactions::account_id.eq_any(ids))
+        .filter(expenses::is_deleted.eq(false))
+        .filter(expenses::is_template.eq(false))
+        .select(expenses::all_columns)
+        .distinct()
+        .load::<Expense>(connection)?;
+
+    Ok(render_expenses(connection, user_id, exps)?
+        .into_iter()
+        .flat_map(|e| {
+            e.transactions
+                .into_iter()
+                .zip(e.calculated_amounts.into_iter())
+                .filter(|(t, _)| t.account_id == account_id)
+                .map(|(t, amount)| (t.date, amount))
+                .collect::<Vec<_>>()
+        })
+        .collect())
+}
+
+// the balances of an account (sorted by date) as seen by its owner, see `RenderedBalance::render`
+pub fn account_checkpoints(
+    connection: &PgConnection,
+    account_id: i32,
+) -> Result<Vec<(DateTime<Utc>, i64)>, diesel::result::Error> {
+    let sync = synchronization_by_account_id(connection, account_id)?;
+    let mut ids = vec![account_id];
+    if let Some(sync) = &sync {
+        ids.push(sync.account1);
+        ids.push(sync.account2);
+    }
+
+    Ok(balances::table
+        .filter(balances::account_id.eq_any(ids))
+        .order(balances::date)
+        .load::<Balance>(connection)?
+        .into_iter()
+        .map(|b| match &sync {
+            Some(sync) if b.account_id != account_id && sync.invert => (b.date, -b.amount),
+            _ => (b.date, b.amount),
+        })
+        .collect())
+}
@@ -0,0 +1,138 @@
+use crate::schema::*;
+
+use diesel::prelude::*;
+use log::info;
+use sha2::{Digest, Sha256};
+
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+// file types that are accepted as receipts, identified by their magic bytes
+const SIGNATURES: &[(&[u8], &str)] = &[
+    (b"%PDF-", "pdf"),
+    (b"\x89PNG\r\n\x1a\n", "png"),
+    (b"\xff\xd8\xff", "jpg"),
+    (b"GIF87a", "gif"),
+    (b"GIF89a", "gif"),
+    (b"II*\x00", "tiff"),
+    (b"MM\x00*", "tiff"),
+];
+
+// guesses the type of a file from its contents and returns the usual extension
+pub fn sniff(data: &[u8]) -> Option<&'static str> {
+    if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        return Some("webp");
+    }
+
+    SIGNATURES
+        .iter()
+        .find(|(magic, _)| data.starts_with(magic))
+        .map(|(_, ext)| *ext)
+}
+
+// receipts are stored content-addressed, i.e. named after the sha256 of their contents (plus an extension).
+// Uploading the same file twice therefore only stores it once, and it may be referenced by multiple `ExpenseReceipt`s.
+#[derive(Debug, Clone)]
+pub struct ReceiptStore {
+    root: PathBuf,
+}
+
+impl ReceiptStore {
+    pub fn new<P: Into<PathBuf>>(root: P) -> ReceiptStore {
+        ReceiptStore { root: root.into() }
+    }
+
+    // only plain file names are allowed, so that receipts cannot point outside of the store
+    fn path(&self, file_name: &str) -> io::Result<PathBuf> {
+        if file_name.is_empty()
+            || file_name.starts_with('.')
+            || file_name.contains(|c| c == '/' || c == '\\')
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid receipt file name '{}'", file_name),
+            ));
+        }
+
+        Ok(self.root.join(file_name))
+    }
+
+    // stores the data and returns the file name, or `None` if it does not look like a supported file type
+    pub fn put(&self, data: &[u8]) -> io::Result<Option<String>> {
+        let ext = match sniff(data) {
+            Some(ext) => ext,
+            None => return Ok(None),
+        };
+
+        let file_name = format!("{:x}.{}", Sha256::digest(data), ext);
+        let path = self.path(&file_name)?;
+
+        if !path.exists() {
+            fs::create_dir_all(&self.root)?;
+
+            // write to a temporary file first so that there are never partial receipts in the store
+            let tmp = self.root.join(format!(".{}.tmp", file_name));
+            fs::write(&tmp, data)?;
+            fs::rename(&tmp, &path)?;
+        }
+
+        Ok(Some(file_name))
+    }
+
+    pub fn get(&self, file_name: &str) -> io::Result<Vec<u8>> {
+        fs::read(self.path(file_name)?)
+    }
+
+    // removes the file if no receipt references it anymore, returns whether it was removed
+    pub fn remove_if_unreferenced(
+        &self,
+        connection: &PgConnection,
+        file_name: &str,
+    ) -> Result<bool, Box<dyn Error>> {
+        let count = expense_receipts::table
+            .filter(expense_receipts::file_name.eq(file_name))
+            .select(diesel::dsl::count(expense_receipts::id))
+            .get_result::<i64>(connection)?;
+
+        let path = self.path(file_name)?;
+        if count > 0 || !path.exists() {
+            return Ok(false);
+        }
+
+        fs::remove_file(path)?;
+        info!("removed unreferenced receipt {}", file_name);
+
+        Ok(true)
+    }
+
+    // removes all files that are not referenced by any receipt, e.g. after expenses were purged
+    pub fn remove_unreferenced(&self, connection: &PgConnection) -> Result<usize, Box<dyn Error>> {
+        if !self.root.exists() {
+            return Ok(0);
+        }
+
+        let referenced = expense_receipts::table
+            .select(expense_receipts::file_name)
+            .load::<String>(connection)?
+            .into_iter()
+            .collect::<HashSet<_>>();
+
+        let mut count = 0;
+        for entry in fs::read_dir(&self.root)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+
+            if entry.file_type()?.is_file() && !name.starts_with('.') && !referenced.contains(&name)
+            {
+                fs::remove_file(entry.path())?;
+                count += 1;
+            }
+        }
+
+        info!("removed {} unreferenced receipts", count);
+        Ok(count)
+    }
+}
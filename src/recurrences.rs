@@ -0,0 +1,272 @@
+use crate::queries;
+use crate::reports::Resolution;
+use crate::schema::*;
+
+use chrono::{DateTime, Duration, Utc};
+use diesel::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Interval {
+    Weekly,
+    Monthly,
+    Quarterly,
+    Yearly,
+}
+
+impl Interval {
+    const ALL: [Interval; 4] = [
+        Interval::Weekly,
+        Interval::Monthly,
+        Interval::Quarterly,
+        Interval::Yearly,
+    ];
+
+    // usual number of days between two occurrences and by how much that may vary (e.g. because of weekends)
+    fn days(&self) -> (i64, i64) {
+        match self {
+            Interval::Weekly => (7, 1),
+            Interval::Monthly => (30, 4),
+            Interval::Quarterly => (91, 7),
+            Interval::Yearly => (365, 10),
+        }
+    }
+
+    pub fn next(&self, date: DateTime<Utc>) -> DateTime<Utc> {
+        self.nth(date, 1)
+    }
+
+    // `n` intervals after `start`, see `Resolution::nth`
+    pub fn nth(&self, start: DateTime<Utc>, n: u32) -> DateTime<Utc> {
+        let months = match self {
+            Interval::Weekly => return Resolution::Week.nth(start, n),
+            Interval::Monthly => 1,
+            Interval::Quarterly => 3,
+            Interval::Yearly => 12,
+        };
+
+        Resolution::Month.nth(start, months * n)
+    }
+}
+
+// an expense as seen by the user, reduced to what is needed to find recurrences
+#[derive(Debug, Clone)]
+pub struct Occurrence {
+    pub expense_id: i32,
+    pub account_id: i32,
+    pub date: DateTime<Utc>,
+    pub amount: i64,
+    pub store: String,
+    pub statement: String,
+}
+
+// expenses that seem to happen regularly, e.g. subscriptions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Recurrence {
+    pub key: String, // the store or the beginning of the statements
+    pub interval: Interval,
+    pub amount: i64, // median
+    pub account_id: i32,
+    pub expense_ids: Vec<i32>, // oldest first
+    pub last: DateTime<Utc>,
+    pub next: DateTime<Utc>,     // when the next occurrence is expected
+    pub statement_regex: String, // suggestion for a delivery rule
+}
+
+// the first few words of a bank statement, without the parts that usually change (dates, reference numbers, ...)
+pub fn statement_prefix(statement: &str) -> String {
+    statement
+        .split_whitespace()
+        .filter(|w| !w.chars().any(|c| c.is_ascii_digit()))
+        .take(3)
+        .map(|w| w.to_lowercase())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn key(o: &Occurrence) -> String {
+    if o.store.trim().is_empty() {
+        statement_prefix(&o.statement)
+    } else {
+        o.store.trim().to_lowercase()
+    }
+}
+
+fn median(values: &[i64]) -> i64 {
+    let mut values = values.to_vec();
+    values.sort_unstable();
+    values[values.len() / 2]
+}
+
+// the interval that three quarters of the gaps between the (sorted) dates match, if any
+pub fn detect_interval(dates: &[DateTime<Utc>]) -> Option<Interval> {
+    let gaps = dates
+        .windows(2)
+        .map(|w| (w[1] - w[0]).num_days())
+        .collect::<Vec<_>>();
+    if gaps.is_empty() {
+        return None;
+    }
+
+    Interval::ALL.iter().cloned().find(|i| {
+        let (days, tolerance) = i.days();
+        let matching = gaps
+            .iter()
+            .filter(|g| (**g - days).abs() <= tolerance)
+            .count();
+        matching * 4 >= gaps.len() * 3
+    })
+}
+
+// groups the occurrences by account and store (or statement) and looks for groups with similar amounts (within 20%
+// of the median) at regular intervals. At least three occurrences are needed.
+pub fn detect(occurrences: &[Occurrence]) -> Vec<Recurrence> {
+    let mut groups = BTreeMap::<(i32, String), Vec<&Occurrence>>::new();
+    for o in occurrences.iter() {
+        let key = key(o);
+        if !key.is_empty() {
+            groups.entry((o.account_id, key)).or_default().push(o);
+        }
+    }
+
+    let mut recurrences = Vec::new();
+    for ((account_id, key), mut group) in groups.into_iter() {
+        let amount = median(&group.iter().map(|o| o.amount).collect::<Vec<_>>());
+        group.retain(|o| (o.amount - amount).abs() * 5 <= amount.abs());
+        if group.len() < 3 {
+            continue;
+        }
+        group.sort_by_key(|o| o.date);
+
+        if let Some(interval) = detect_interval(&group.iter().map(|o| o.date).collect::<Vec<_>>()) {
+            let last = group.last().unwrap();
+            let prefix = statement_prefix(&last.statement);
+            let statement_regex = if prefix.is_empty() {
+                format!("(?i){}", regex::escape(&key))
+            } else {
+                let words = prefix.split(' ').map(regex::escape).collect::<Vec<_>>();
+                format!("(?i){}", words.join(r"\s+"))
+            };
+
+            recurrences.push(Recurrence {
+                key,
+                interval,
+                amount,
+                account_id,
+                expense_ids: group.iter().map(|o| o.expense_id).collect(),
+                last: last.date,
+                next: interval.next(last.date),
+                statement_regex,
+            });
+        }
+    }
+
+    recurrences
+}
+
+// the user's expenses (without deleted ones and templates), one occurrence for each transaction on their own accounts.
+// only the last three years are considered, which is enough to find yearly recurrences
+pub fn occurrences(
+    connection: &PgConnection,
+    user_id: i32,
+) -> Result<Vec<Occurrence>, diesel::result::Error> {
+    // after rendering, transactions on synchronized accounts appear on the user's own accounts
+    let own = accounts::table
+        .filter(accounts::user_id.eq(user_id))
+        .select(accounts::id)
+        .load::<i32>(connection)?;
+
+    let to = Utc::now();
+    let from = to - Duration::days(3 * 365 + Interval::Yearly.days().1);
+    let exps = queries::booked_expenses(connection, user_id, from, to)?;
+
+    Ok(queries::render_expenses(connection, user_id, exps)?
+        .into_iter()
+        .flat_map(|e| {
+            let expense_id = e.info.id;
+            let store = e.info.store;
+
+            e.transactions
+                .into_iter()
+                .zip(e.calculated_amounts.into_iter())
+                .filter(|(t, _)| own.contains(&t.account_id))
+                .filter(|(t, _)| t.date >= from && t.date < to)
+                .map(|(t, amount)| Occurrence {
+                    expense_id,
+                    account_id: t.account_id,
+                    date: t.date,
+                    amount,
+                    store: store.clone(),
+                    statement: t.statement,
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    // dates that start on 2021-01-01 and are separated by the given numbers of days
+    fn dates(gaps: &[i64]) -> Vec<DateTime<Utc>> {
+        let start = Utc.ymd(2021, 1, 1).and_hms(0, 0, 0);
+        std::iter::once(0)
+            .chain(gaps.iter().scan(0, |sum, g| {
+                *sum += g;
+                Some(*sum)
+            }))
+            .map(|d| start + Duration::days(d))
+            .collect()
+    }
+
+    #[test]
+    fn detect_interval_allows_a_quarter_of_the_gaps_to_differ() {
+        assert_eq!(
+            detect_interval(&dates(&[30, 31, 28, 60])),
+            Some(Interval::Monthly)
+        );
+        assert_eq!(detect_interval(&dates(&[30, 31, 60, 60])), None);
+    }
+
+    #[test]
+    fn detect_interval_uses_the_tolerance() {
+        assert_eq!(detect_interval(&dates(&[6, 8, 7])), Some(Interval::Weekly));
+        assert_eq!(
+            detect_interval(&dates(&[88, 95])),
+            Some(Interval::Quarterly)
+        );
+        assert_eq!(detect_interval(&dates(&[20, 20, 20])), None);
+    }
+
+    #[test]
+    fn monthly_intervals_keep_the_day() {
+        let start = Utc.ymd(2021, 1, 31).and_hms(0, 0, 0);
+        assert_eq!(
+            Interval::Monthly.next(start),
+            Utc.ymd(2021, 2, 28).and_hms(0, 0, 0)
+        );
+        assert_eq!(
+            Interval::Monthly.nth(start, 2),
+            Utc.ymd(2021, 3, 31).and_hms(0, 0, 0)
+        );
+        assert_eq!(
+            Interval::Quarterly.nth(start, 1),
+            Utc.ymd(2021, 4, 30).and_hms(0, 0, 0)
+        );
+        assert_eq!(
+            Interval::Weekly.nth(start, 2),
+            Utc.ymd(2021, 2, 14).and_hms(0, 0, 0)
+        );
+    }
+
+    #[test]
+    fn detect_interval_needs_two_dates() {
+        assert_eq!(detect_interval(&dates(&[])), None);
+    }
+}
@@ -51,6 +51,44 @@ impl RenderedBalance {
     }
 }
 
+pub type RenderedTransaction = ExpenseTransaction;
+
+impl RenderedTransaction {
+    pub fn render(
+        mut transaction: ExpenseTransaction,
+        account_sync: Option<&AccountSynchronization>,
+    ) -> RenderedTransaction {
+        if let Some(sync) = account_sync {
+            if sync.invert {
+                transaction.amount = transaction.amount.map(|y| -y);
+                transaction.fraction = transaction.fraction.map(|y| -y);
+            }
+
+            transaction.account_id = if sync.account1 == transaction.account_id {
+                sync.account2
+            } else {
+                sync.account1
+            };
+        }
+
+        transaction
+    }
+}
+
+impl NewBalance {
+    // the reverse of `RenderedBalance::render`: balances of synchronized accounts are stored on `account1`
+    pub fn unrender(mut self, account_sync: Option<&AccountSynchronization>) -> NewBalance {
+        if let Some(sync) = account_sync {
+            if sync.account2 == self.account_id {
+                self.account_id = sync.account1;
+                self.amount *= if sync.invert { -1 } else { 1 };
+            }
+        }
+
+        self
+    }
+}
+
 impl RenderedCategory {
     pub fn render(category: Category, replaces: &[CategoryReplacement]) -> RenderedCategory {
         RenderedCategory {
@@ -122,21 +160,7 @@ impl RenderedExpense {
     ) -> RenderedExpense {
         let transactions = transactions
             .into_iter()
-            .map(|(mut t, a, acs)| {
-                if let Some(acs) = &acs {
-                    if acs.invert {
-                        t.amount = t.amount.map(|y| -y);
-                        t.fraction = t.fraction.map(|y| -y);
-                    }
-
-                    t.account_id = if acs.account1 == t.account_id {
-                        acs.account2
-                    } else {
-                        acs.account1
-                    };
-                }
-                (t, a, acs)
-            })
+            .map(|(t, a, acs)| (RenderedTransaction::render(t, acs.as_ref()), a, acs))
             .collect::<Vec<_>>();
 
         let (total_amount, calculated_amounts) = calculate_total_amount(uid, &info, &transactions);
@@ -182,21 +206,11 @@ impl RenderedExpense {
             .skip_while(|(t, _, _)| t.expense_id != info.id)
             .take_while(|(t, _, _)| t.expense_id == info.id)
             .map(|(t, a, acs)| {
-                let mut t = t.clone();
-
-                if let Some(acs) = acs {
-                    if acs.invert {
-                        t.amount = t.amount.map(|y| -y);
-                        t.fraction = t.fraction.map(|y| -y);
-                    }
-
-                    t.account_id = if acs.account1 == t.account_id {
-                        acs.account2
-                    } else {
-                        acs.account1
-                    };
-                }
-                (t, a.clone(), acs.clone())
+                (
+                    RenderedTransaction::render(t.clone(), acs.as_ref()),
+                    a.clone(),
+                    acs.clone(),
+                )
             })
             .collect::<Vec<_>>();
 
@@ -0,0 +1,622 @@
+use crate::enums::{AccountAvailability, AccountKind, AccountRisk, BudgetPeriod};
+use crate::models::{Account, Budget};
+
+use chrono::{DateTime, Datelike, Duration, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Resolution {
+    Day,
+    Week,
+    Month,
+}
+
+impl Resolution {
+    pub fn parse(s: &str) -> Option<Resolution> {
+        match s.to_lowercase().as_ref() {
+            "day" => Some(Resolution::Day),
+            "week" => Some(Resolution::Week),
+            "month" => Some(Resolution::Month),
+            _ => None,
+        }
+    }
+
+    pub fn next(&self, date: DateTime<Utc>) -> DateTime<Utc> {
+        self.nth(date, 1)
+    }
+
+    // `n` steps after `start`, always counted from `start` so that months do not drift towards their shorter ends
+    pub fn nth(&self, start: DateTime<Utc>, n: u32) -> DateTime<Utc> {
+        match self {
+            Resolution::Day => start + Duration::days(n as i64),
+            Resolution::Week => start + Duration::weeks(n as i64),
+            Resolution::Month => add_months(start, n),
+        }
+    }
+}
+
+// keeps the day of the month if possible, e.g. Jan 31st + 1 month is the last day of February,
+// but Jan 31st + 2 months is Mar 31st
+pub fn add_months(date: DateTime<Utc>, months: u32) -> DateTime<Utc> {
+    let month0 = date.month0() + months;
+    let year = date.year() + (month0 / 12) as i32;
+    let month = month0 % 12 + 1;
+
+    let mut day = date.day();
+    loop {
+        if let Some(d) = Utc.ymd_opt(year, month, day).single() {
+            return d.and_time(date.time()).unwrap();
+        }
+        day -= 1;
+    }
+}
+
+// `from`, `from` + resolution, ... up to (and including) `to`
+pub fn time_steps(
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    resolution: Resolution,
+) -> Vec<DateTime<Utc>> {
+    let mut steps = Vec::new();
+    let mut date = from;
+
+    while date <= to {
+        steps.push(date);
+        date = resolution.nth(from, steps.len() as u32);
+    }
+
+    steps
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DataPoint {
+    pub date: DateTime<Utc>,
+    pub amount: i64,
+}
+
+// the balance of an account at the given dates (which have to be sorted), computed from the nearest (in time) checkpoint
+// by adding or subtracting the flows in between. Like `Balance`, the balance at a date includes the flows before it.
+// Without checkpoints, the account is assumed to start with a balance of 0.
+pub fn balance_history(
+    checkpoints: &[(DateTime<Utc>, i64)],
+    flows: &[(DateTime<Utc>, i64)],
+    dates: &[DateTime<Utc>],
+) -> Vec<DataPoint> {
+    // sum of all flows strictly before `date`
+    let mut flows = flows.to_vec();
+    flows.sort_by_key(|(d, _)| *d);
+    let mut cumulative = Vec::with_capacity(flows.len() + 1);
+    cumulative.push(0);
+    for (_, amount) in flows.iter() {
+        cumulative.push(cumulative.last().unwrap() + amount);
+    }
+    let flows_before = |date: DateTime<Utc>| {
+        let i = flows
+            .binary_search_by(|(d, _)| {
+                if *d < date {
+                    Ordering::Less
+                } else {
+                    Ordering::Greater
+                }
+            })
+            .unwrap_or_else(|i| i);
+        cumulative[i]
+    };
+
+    dates
+        .iter()
+        .map(|date| {
+            let nearest = checkpoints
+                .iter()
+                .min_by_key(|(d, _)| (*d - *date).num_seconds().abs());
+
+            let amount = match nearest {
+                Some((d, amount)) => amount + flows_before(*date) - flows_before(*d),
+                None => flows_before(*date),
+            };
+
+            DataPoint {
+                date: *date,
+                amount,
+            }
+        })
+        .collect()
+}
+
+// amounts are stored in cents
+pub fn format_amount(amount: i64) -> String {
+    format!("{:.2}", amount as f64 / 100.0)
+}
+
+// an interval between two consecutive balances of an account in which the transactions do not explain the change of the balance
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Discrepancy {
+    pub account_id: i32,
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+    pub balance_change: i64,
+    pub transaction_sum: i64,
+    pub difference: i64, // balance_change - transaction_sum, i.e. what is missing
+}
+
+// compares consecutive checkpoints (sorted by date) of an account with the flows in between
+pub fn reconcile(
+    account_id: i32,
+    checkpoints: &[(DateTime<Utc>, i64)],
+    flows: &[(DateTime<Utc>, i64)],
+) -> Vec<Discrepancy> {
+    checkpoints
+        .windows(2)
+        .filter_map(|w| {
+            let (from, start) = w[0];
+            let (to, end) = w[1];
+
+            // balances include everything that happened before their date
+            let transaction_sum = flows
+                .iter()
+                .filter(|(d, _)| *d >= from && *d < to)
+                .map(|(_, amount)| amount)
+                .sum::<i64>();
+            let balance_change = end - start;
+
+            if balance_change == transaction_sum {
+                None
+            } else {
+                Some(Discrepancy {
+                    account_id,
+                    from,
+                    to,
+                    balance_change,
+                    transaction_sum,
+                    difference: balance_change - transaction_sum,
+                })
+            }
+        })
+        .collect()
+}
+
+// how expenses are assigned to points in time in reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum View {
+    Cash,    // when the money actually moved, i.e. at the dates of the transactions
+    Accrual, // spread evenly over the days of the booking period
+}
+
+impl View {
+    pub fn parse(s: &str) -> Option<View> {
+        match s.to_lowercase().as_ref() {
+            "cash" => Some(View::Cash),
+            "accrual" => Some(View::Accrual),
+            _ => None,
+        }
+    }
+}
+
+// spreads the amount evenly by day over the booking period (including the day of `end`) and sums the parts per month.
+// each part is dated at its first day, the last one gets the rounding error so that nothing is lost.
+pub fn prorate(amount: i64, start: DateTime<Utc>, end: DateTime<Utc>) -> Vec<(DateTime<Utc>, i64)> {
+    let first = start.date().and_hms(0, 0, 0);
+    let last = std::cmp::max(end.date().and_hms(0, 0, 0), first);
+    let days = (last - first).num_days() + 1;
+
+    let mut parts = Vec::new();
+    let mut rest = amount;
+    let mut date = first;
+    while date <= last {
+        let next_month = Resolution::Month.next(month_of(date));
+        let days_in_month = (std::cmp::min(next_month, last + Duration::days(1)) - date).num_days();
+
+        let part = if next_month > last {
+            rest
+        } else {
+            (amount as f64 * days_in_month as f64 / days as f64).round() as i64
+        };
+        rest -= part;
+        parts.push((date, part));
+
+        date = next_month;
+    }
+
+    parts
+}
+
+// midnight of the first day of the month of `date`
+pub fn month_of(date: DateTime<Utc>) -> DateTime<Utc> {
+    Utc.ymd(date.year(), date.month(), 1).and_hms(0, 0, 0)
+}
+
+// an expense as seen by a user, with the (already replaced) categories and their weights
+#[derive(Debug, Clone)]
+pub struct CategorizedAmount {
+    pub date: DateTime<Utc>,
+    pub amount: i64,
+    pub categories: Vec<(i32, f64)>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CategoryAmount {
+    pub category_id: i32,
+    pub amount: i64, // assigned to the category itself
+    pub total: i64,  // including all (transitive) children
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MonthlySpending {
+    pub month: DateTime<Utc>,
+    pub categories: Vec<CategoryAmount>,
+    pub uncategorized: i64,
+}
+
+// splits the amount according to the weights, the last part gets the rounding error so that nothing is lost
+pub fn split_by_weight(amount: i64, weights: &[(i32, f64)]) -> Vec<(i32, i64)> {
+    let sum = weights.iter().map(|(_, w)| w).sum::<f64>();
+    let mut rest = amount;
+
+    weights
+        .iter()
+        .enumerate()
+        .map(|(i, (id, w))| {
+            let part = if i + 1 == weights.len() {
+                rest
+            } else {
+                (amount as f64 * w / sum).round() as i64
+            };
+            rest -= part;
+            (*id, part)
+        })
+        .collect()
+}
+
+// the category itself and all its ancestors, each only once (even if the tree contains a cycle)
+fn ancestors(parents: &HashMap<i32, Option<i32>>, id: i32) -> Vec<i32> {
+    let mut seen = HashSet::new();
+    let mut current = Some(id);
+
+    while let Some(x) = current {
+        if !seen.insert(x) {
+            break;
+        }
+        current = parents.get(&x).cloned().flatten();
+    }
+
+    seen.into_iter().collect()
+}
+
+// totals per month and category, where `parents` maps categories to their parent categories
+pub fn category_spending(
+    expenses: &[CategorizedAmount],
+    parents: &HashMap<i32, Option<i32>>,
+) -> Vec<MonthlySpending> {
+    let mut months = BTreeMap::<DateTime<Utc>, (BTreeMap<i32, (i64, i64)>, i64)>::new();
+
+    for e in expenses.iter() {
+        let (categories, uncategorized) = months.entry(month_of(e.date)).or_default();
+
+        if e.categories.is_empty() {
+            *uncategorized += e.amount;
+            continue;
+        }
+
+        for (id, amount) in split_by_weight(e.amount, &e.categories).into_iter() {
+            categories.entry(id).or_default().0 += amount;
+            for a in ancestors(parents, id).into_iter() {
+                categories.entry(a).or_default().1 += amount;
+            }
+        }
+    }
+
+    months
+        .into_iter()
+        .map(|(month, (categories, uncategorized))| MonthlySpending {
+            month,
+            categories: categories
+                .into_iter()
+                .map(|(category_id, (amount, total))| CategoryAmount {
+                    category_id,
+                    amount,
+                    total,
+                })
+                .collect(),
+            uncategorized,
+        })
+        .collect()
+}
+
+// the period that contains `date`, as [start, end)
+pub fn budget_period(period: BudgetPeriod, date: DateTime<Utc>) -> (DateTime<Utc>, DateTime<Utc>) {
+    let (first_month, months) = match period {
+        BudgetPeriod::Month => (date.month(), 1),
+        BudgetPeriod::Quarter => ((date.month() - 1) / 3 * 3 + 1, 3),
+        BudgetPeriod::Year => (1, 12),
+    };
+
+    let start = Utc.ymd(date.year(), first_month, 1).and_hms(0, 0, 0);
+    let end = add_months(start, months);
+
+    (start, end)
+}
+
+// sum of the expenses in [from, to) that belong to `root` or one of its subcategories
+fn subtree_total(
+    expenses: &[CategorizedAmount],
+    parents: &HashMap<i32, Option<i32>>,
+    root: i32,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> i64 {
+    expenses
+        .iter()
+        .filter(|e| e.date >= from && e.date < to)
+        .flat_map(|e| split_by_weight(e.amount, &e.categories))
+        .filter(|(id, _)| ancestors(parents, *id).contains(&root))
+        .map(|(_, amount)| amount)
+        .sum()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BudgetStatus {
+    pub budget: Budget,
+    pub period_start: DateTime<Utc>,
+    pub period_end: DateTime<Utc>,
+    pub available: i64, // amount of the budget plus what was rolled over from earlier periods
+    pub spent: i64,
+    pub remaining: i64,
+    pub percent_used: Option<f64>, // none if nothing is available
+    pub projected_overrun: i64, // by how much `available` will be exceeded if spending continues at this pace
+}
+
+// compares the spending (expenses are negative, so refunds reduce it) in the current period with the budget.
+// `expenses` have to contain everything since the start of the budget if it rolls over.
+pub fn budget_status(
+    budget: &Budget,
+    expenses: &[CategorizedAmount],
+    parents: &HashMap<i32, Option<i32>>,
+    now: DateTime<Utc>,
+) -> BudgetStatus {
+    let spent_in = |from, to| -subtree_total(expenses, parents, budget.category_id, from, to);
+    let (period_start, period_end) = budget_period(budget.period, now);
+
+    let mut carry = 0;
+    if budget.rollover {
+        // overspending is not carried over, only what was left
+        let (mut start, mut end) = budget_period(budget.period, budget.start);
+        while start < period_start {
+            carry = std::cmp::max(0, carry + budget.amount - spent_in(start, end));
+            let next = budget_period(budget.period, end);
+            start = next.0;
+            end = next.1;
+        }
+    }
+    let available = budget.amount + carry;
+
+    let spent = spent_in(period_start, period_end);
+    let elapsed = (now - period_start).num_seconds() as f64
+        / (period_end - period_start).num_seconds() as f64;
+    let projected = if elapsed > 0.0 {
+        (spent as f64 / elapsed).round() as i64
+    } else {
+        spent
+    };
+
+    BudgetStatus {
+        budget: budget.clone(),
+        period_start,
+        period_end,
+        available,
+        spent,
+        remaining: available - spent,
+        percent_used: if available > 0 {
+            Some(spent as f64 * 100.0 / available as f64)
+        } else {
+            None
+        },
+        projected_overrun: std::cmp::max(0, projected - available),
+    }
+}
+
+// debts and credit cards are what the user owes, everything else is what they own
+pub fn is_liability(kind: AccountKind) -> bool {
+    matches!(kind, AccountKind::Debt | AccountKind::Credit)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NetWorth {
+    pub date: DateTime<Utc>,
+    pub assets: i64,
+    pub liabilities: i64,
+    pub net: i64,
+    pub by_risk: BTreeMap<AccountRisk, i64>,
+    pub by_availability: BTreeMap<AccountAvailability, i64>,
+    pub by_kind: BTreeMap<AccountKind, i64>,
+}
+
+// sums the balances of the accounts, which have to be given for the same dates (see `balance_history`).
+// liabilities are usually negative. The groups contain assets and liabilities, i.e. they add up to `net`.
+pub fn net_worth(accounts: &[(Account, Vec<DataPoint>)], dates: &[DateTime<Utc>]) -> Vec<NetWorth> {
+    dates
+        .iter()
+        .enumerate()
+        .map(|(i, date)| {
+            let mut nw = NetWorth {
+                date: *date,
+                assets: 0,
+                liabilities: 0,
+                net: 0,
+                by_risk: BTreeMap::new(),
+                by_availability: BTreeMap::new(),
+                by_kind: BTreeMap::new(),
+            };
+
+            for (acc, history) in accounts.iter() {
+                let amount = history[i].amount;
+
+                if is_liability(acc.kind) {
+                    nw.liabilities += amount;
+                } else {
+                    nw.assets += amount;
+                }
+                nw.net += amount;
+                *nw.by_risk.entry(acc.risk).or_default() += amount;
+                *nw.by_availability.entry(acc.availability).or_default() += amount;
+                *nw.by_kind.entry(acc.kind).or_default() += amount;
+            }
+
+            nw
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ForecastPoint {
+    pub date: DateTime<Utc>,
+    pub template_id: i32,
+    pub change: i64,
+    pub amount: i64, // balance after the change
+    pub below_zero: bool,
+    pub below_threshold: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Forecast {
+    pub account_id: i32,
+    pub start: DataPoint,
+    pub points: Vec<ForecastPoint>,
+}
+
+// projects the balance of an account from `start` by applying the expected changes (date, template, amount) in order
+pub fn forecast(
+    account_id: i32,
+    start: DataPoint,
+    changes: &[(DateTime<Utc>, i32, i64)],
+    threshold: i64,
+) -> Forecast {
+    let mut changes = changes.to_vec();
+    changes.sort_by_key(|(date, template_id, _)| (*date, *template_id));
+
+    let mut amount = start.amount;
+    let points = changes
+        .into_iter()
+        .map(|(date, template_id, change)| {
+            amount += change;
+            ForecastPoint {
+                date,
+                template_id,
+                change,
+                amount,
+                below_zero: amount < 0,
+                below_threshold: amount < threshold,
+            }
+        })
+        .collect();
+
+    Forecast {
+        account_id,
+        start,
+        points,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(d: u32) -> DateTime<Utc> {
+        Utc.ymd(2021, 1, d).and_hms(0, 0, 0)
+    }
+
+    #[test]
+    fn monthly_time_steps_do_not_drift() {
+        let start = Utc.ymd(2021, 1, 31).and_hms(12, 0, 0);
+        let end = Utc.ymd(2021, 4, 30).and_hms(12, 0, 0);
+
+        let days = time_steps(start, end, Resolution::Month)
+            .iter()
+            .map(|d| (d.month(), d.day()))
+            .collect::<Vec<_>>();
+        assert_eq!(days, vec![(1, 31), (2, 28), (3, 31), (4, 30)]);
+        assert_eq!(
+            add_months(start, 13),
+            Utc.ymd(2022, 2, 28).and_hms(12, 0, 0)
+        );
+    }
+
+    #[test]
+    fn reconcile_reports_unexplained_changes() {
+        let checkpoints = [(day(1), 1000), (day(10), 1500), (day(20), 1500)];
+        let flows = [(day(1), 200), (day(5), 300), (day(10), -100)];
+
+        let discrepancies = reconcile(7, &checkpoints, &flows);
+        assert_eq!(discrepancies.len(), 1);
+        let d = &discrepancies[0];
+        assert_eq!((d.account_id, d.from, d.to), (7, day(10), day(20)));
+        assert_eq!((d.balance_change, d.transaction_sum), (0, -100));
+        assert_eq!(d.difference, 100);
+    }
+
+    #[test]
+    fn reconcile_needs_two_checkpoints() {
+        assert!(reconcile(7, &[(day(1), 1000)], &[(day(2), 50)]).is_empty());
+    }
+
+    #[test]
+    fn split_by_weight_loses_no_cents() {
+        let parts = split_by_weight(1000, &[(1, 1.0), (2, 1.0), (3, 1.0)]);
+        assert_eq!(parts, vec![(1, 333), (2, 333), (3, 334)]);
+
+        let parts = split_by_weight(-999, &[(1, 0.5), (2, 0.25), (3, 0.25)]);
+        assert_eq!(parts.iter().map(|(_, a)| a).sum::<i64>(), -999);
+        assert_eq!(parts[0], (1, -500));
+    }
+
+    #[test]
+    fn split_by_weight_with_a_single_category() {
+        assert_eq!(split_by_weight(1234, &[(5, 0.3)]), vec![(5, 1234)]);
+    }
+
+    #[test]
+    fn prorate_by_days_per_month() {
+        let start = Utc.ymd(2021, 1, 20).and_hms(14, 30, 0);
+        let end = Utc.ymd(2021, 3, 10).and_hms(9, 0, 0);
+
+        // 12 days in january, 28 in february and 10 in march
+        let parts = prorate(10000, start, end);
+        assert_eq!(
+            parts,
+            vec![
+                (day(20), 2400),
+                (Utc.ymd(2021, 2, 1).and_hms(0, 0, 0), 5600),
+                (Utc.ymd(2021, 3, 1).and_hms(0, 0, 0), 2000),
+            ]
+        );
+    }
+
+    #[test]
+    fn prorated_cents_sum_to_the_total() {
+        let start = Utc.ymd(2021, 1, 1).and_hms(0, 0, 0);
+        let end = Utc.ymd(2021, 12, 31).and_hms(0, 0, 0);
+
+        for amount in [1, 999, 1000, -12345, 100001].iter() {
+            let parts = prorate(*amount, start, end);
+            assert_eq!(parts.len(), 12);
+            assert_eq!(parts.iter().map(|(_, a)| a).sum::<i64>(), *amount);
+        }
+    }
+
+    #[test]
+    fn prorate_a_single_day() {
+        let date = Utc.ymd(2021, 1, 15).and_hms(12, 0, 0);
+        assert_eq!(prorate(4711, date, date), vec![(day(15), 4711)]);
+    }
+}
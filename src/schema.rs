@@ -1,5 +1,18 @@
 #![allow(warnings)]
 
+table! {
+    use diesel::sql_types::*;
+    use crate::enums::*;
+
+    account_sync_invitations (id) {
+        id -> Int4,
+        account -> Int4,
+        other_account -> Int4,
+        inviter -> Int4,
+        invitee -> Int4,
+    }
+}
+
 table! {
     use diesel::sql_types::*;
     use crate::enums::*;
@@ -44,6 +57,21 @@ table! {
     }
 }
 
+table! {
+    use diesel::sql_types::*;
+    use crate::enums::*;
+
+    budgets (id) {
+        id -> Int4,
+        user_id -> Int4,
+        category_id -> Int4,
+        period -> Budget_period,
+        amount -> Int8,
+        rollover -> Bool,
+        start -> Timestamptz,
+    }
+}
+
 table! {
     use diesel::sql_types::*;
     use crate::enums::*;
@@ -174,6 +202,8 @@ table! {
 
 joinable!(accounts -> users (user_id));
 joinable!(balances -> accounts (account_id));
+joinable!(budgets -> categories (category_id));
+joinable!(budgets -> users (user_id));
 joinable!(categories -> users (user_id));
 joinable!(category_replacements -> users (user_id));
 joinable!(delivery_rules -> accounts (account_id));
@@ -188,9 +218,11 @@ joinable!(expense_transactions -> accounts (account_id));
 joinable!(expense_transactions -> expenses (expense_id));
 
 allow_tables_to_appear_in_same_query!(
+    account_sync_invitations,
     account_synchronizations,
     accounts,
     balances,
+    budgets,
     categories,
     category_replacements,
     delivery_rules,
@@ -0,0 +1,227 @@
+use crate::models::*;
+use crate::queries;
+use crate::reports::{format_amount, split_by_weight};
+use crate::schema::*;
+
+use chrono::{DateTime, TimeZone, Utc};
+use diesel::prelude::*;
+use printpdf::{BuiltinFont, Mm, PdfDocument};
+use serde::{Deserialize, Serialize};
+
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::io::BufWriter;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaxEntry {
+    pub expense_id: i32,
+    pub date: DateTime<Utc>,
+    pub title: String,
+    pub store: String,
+    pub amount: i64, // the part of the expense that belongs to the category
+    pub receipts: Vec<ExpenseReceipt>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaxCategory {
+    pub category_id: Option<i32>, // none for expenses without categories
+    pub name: String,
+    pub total: i64,
+    pub entries: Vec<TaxEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TaxReport {
+    pub year: i32,
+    pub total: i64,
+    pub categories: Vec<TaxCategory>,
+}
+
+// the user's tax relevant expenses booked in `year`, split between their categories according to the weights.
+// `None` if the year cannot be represented
+pub fn tax_report(
+    connection: &PgConnection,
+    user_id: i32,
+    year: i32,
+) -> Result<Option<TaxReport>, diesel::result::Error> {
+    let (from, to) = match (
+        Utc.ymd_opt(year, 1, 1).single(),
+        year.checked_add(1)
+            .and_then(|y| Utc.ymd_opt(y, 1, 1).single()),
+    ) {
+        (Some(from), Some(to)) => (from.and_hms(0, 0, 0), to.and_hms(0, 0, 0)),
+        _ => return Ok(None),
+    };
+
+    let exps = queries::booked_expenses(connection, user_id, from, to)?
+        .into_iter()
+        .filter(|e| e.is_tax_relevant)
+        .filter(|e| e.booking_start >= from && e.booking_start < to)
+        .collect();
+    let mut exps = queries::render_expenses(connection, user_id, exps)?;
+    exps.sort_by_key(|e| (e.info.booking_start, e.info.id));
+
+    let names = categories::table
+        .select((categories::id, categories::name))
+        .load::<(i32, String)>(connection)?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+
+    let mut categories = BTreeMap::<Option<i32>, Vec<TaxEntry>>::new();
+    for e in exps.into_iter() {
+        let entry = |amount| TaxEntry {
+            expense_id: e.info.id,
+            date: e.info.booking_start,
+            title: e.info.title.clone(),
+            store: e.info.store.clone(),
+            amount,
+            receipts: e.receipts.clone(),
+        };
+
+        if e.categories.is_empty() {
+            categories
+                .entry(None)
+                .or_default()
+                .push(entry(e.total_amount));
+        } else {
+            let weights = e
+                .categories
+                .iter()
+                .map(|c| (c.category_id, c.weight))
+                .collect::<Vec<_>>();
+            for (id, amount) in split_by_weight(e.total_amount, &weights).into_iter() {
+                categories.entry(Some(id)).or_default().push(entry(amount));
+            }
+        }
+    }
+
+    let categories = categories
+        .into_iter()
+        .map(|(category_id, entries)| TaxCategory {
+            category_id,
+            name: category_id
+                .and_then(|id| names.get(&id).cloned())
+                .unwrap_or_else(|| "Uncategorized".into()),
+            total: entries.iter().map(|e| e.amount).sum(),
+            entries,
+        })
+        .collect::<Vec<_>>();
+
+    Ok(Some(TaxReport {
+        year,
+        total: categories.iter().map(|c| c.total).sum(),
+        categories,
+    }))
+}
+
+fn format_receipts(receipts: &[ExpenseReceipt]) -> String {
+    receipts
+        .iter()
+        .map(|r| r.file_name.clone())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// one row per entry, the totals can be computed by the spreadsheet
+pub fn to_csv(report: &TaxReport) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record(&[
+        "Category", "Date", "Expense", "Title", "Store", "Amount", "Receipts",
+    ])?;
+
+    for c in report.categories.iter() {
+        for e in c.entries.iter() {
+            writer.write_record(&[
+                c.name.clone(),
+                e.date.format("%Y-%m-%d").to_string(),
+                e.expense_id.to_string(),
+                e.title.clone(),
+                e.store.clone(),
+                format_amount(e.amount),
+                format_receipts(&e.receipts),
+            ])?;
+        }
+    }
+
+    Ok(writer.into_inner()?)
+}
+
+// a4 pages with one section per category
+pub fn to_pdf(report: &TaxReport) -> Result<Vec<u8>, Box<dyn Error>> {
+    const WIDTH: f64 = 210.0;
+    const HEIGHT: f64 = 297.0;
+    const MARGIN: f64 = 20.0;
+    const LINE: f64 = 6.0;
+
+    let title = format!("Tax report {}", report.year);
+    let (doc, page, layer) = PdfDocument::new(&title, Mm(WIDTH), Mm(HEIGHT), "Layer 1");
+    let regular = doc.add_builtin_font(BuiltinFont::Helvetica)?;
+    let bold = doc.add_builtin_font(BuiltinFont::HelveticaBold)?;
+
+    let mut layer = doc.get_page(page).get_layer(layer);
+    let mut y = HEIGHT - MARGIN;
+    let next_line = |y: &mut f64| {
+        *y -= LINE;
+        if *y < MARGIN {
+            let (page, l) = doc.add_page(Mm(WIDTH), Mm(HEIGHT), "Layer 1");
+            *y = HEIGHT - MARGIN;
+            Some(doc.get_page(page).get_layer(l))
+        } else {
+            None
+        }
+    };
+
+    layer.use_text(&title, 16.0, Mm(MARGIN), Mm(y), &bold);
+    y -= LINE;
+
+    for c in report.categories.iter() {
+        if let Some(l) = next_line(&mut y) {
+            layer = l;
+        }
+        layer.use_text(&c.name, 12.0, Mm(MARGIN), Mm(y), &bold);
+        layer.use_text(format_amount(c.total), 12.0, Mm(150.0), Mm(y), &bold);
+
+        for e in c.entries.iter() {
+            if let Some(l) = next_line(&mut y) {
+                layer = l;
+            }
+            let text = if e.store.is_empty() {
+                e.title.clone()
+            } else {
+                format!("{} ({})", e.title, e.store)
+            };
+            let text = text.chars().take(60).collect::<String>();
+            layer.use_text(
+                e.date.format("%Y-%m-%d").to_string(),
+                9.0,
+                Mm(MARGIN),
+                Mm(y),
+                &regular,
+            );
+            layer.use_text(text, 9.0, Mm(45.0), Mm(y), &regular);
+            layer.use_text(format_amount(e.amount), 9.0, Mm(150.0), Mm(y), &regular);
+            layer.use_text(
+                format!("{} receipts", e.receipts.len()),
+                9.0,
+                Mm(170.0),
+                Mm(y),
+                &regular,
+            );
+        }
+
+        y -= LINE;
+    }
+
+    if let Some(l) = next_line(&mut y) {
+        layer = l;
+    }
+    layer.use_text("Total", 12.0, Mm(MARGIN), Mm(y), &bold);
+    layer.use_text(format_amount(report.total), 12.0, Mm(150.0), Mm(y), &bold);
+
+    let mut writer = BufWriter::new(Vec::new());
+    doc.save(&mut writer)?;
+    Ok(writer.into_inner()?)
+}
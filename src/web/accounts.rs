@@ -1,12 +1,68 @@
+use crate::models::*;
+use crate::mutations;
 use crate::queries;
 use crate::rendering::RenderedAccount;
+use crate::reports::{self, DataPoint};
+use crate::schema::*;
 use crate::web::user::UserId;
-use crate::web::util::log_error_and_500;
+use crate::web::util::{is_valid_color, log_error_and_500, normalize_iban, time_steps, TOOL};
 use crate::web::DbConn;
 
+use chrono::{DateTime, Utc};
+use diesel::prelude::*;
+use log::{info, warn};
 use rocket::http::Status;
+use rocket_contrib::databases::diesel;
 use rocket_contrib::json::Json;
 
+// checks and normalizes the user-provided parts of an account
+fn validate(
+    name: &str,
+    color: &Option<String>,
+    iban: &Option<String>,
+) -> Result<Option<String>, Status> {
+    if name.trim().is_empty() {
+        warn!("Account without name");
+        return Err(Status::BadRequest);
+    }
+
+    if let Some(color) = color {
+        if !is_valid_color(color) {
+            warn!("Account with invalid color '{}'", color);
+            return Err(Status::BadRequest);
+        }
+    }
+
+    match iban {
+        Some(iban) if !iban.trim().is_empty() => normalize_iban(iban).map(Some).ok_or_else(|| {
+            warn!("Account with invalid iban '{}'", iban);
+            Status::BadRequest
+        }),
+        _ => Ok(None),
+    }
+}
+
+fn own_account(c: &PgConnection, uid: i32, id: i32) -> Result<Account, Status> {
+    accounts::table
+        .filter(accounts::id.eq(id))
+        .filter(accounts::user_id.eq(uid))
+        .get_result::<Account>(c)
+        .optional()
+        .map_err(|e| log_error_and_500(Box::new(e)))?
+        .ok_or(Status::NotFound)
+}
+
+fn render(c: &PgConnection, id: i32) -> Result<RenderedAccount, Status> {
+    let (account, syncing) = queries::account_by_id(c, id)
+        .map_err(|e| log_error_and_500(Box::new(e)))?
+        .ok_or(Status::NotFound)?;
+
+    Ok(RenderedAccount {
+        info: account,
+        synchronization: syncing,
+    })
+}
+
 #[get("/accounts?<offset>&<count>")]
 pub async fn list(
     _uid: UserId,
@@ -35,85 +91,180 @@ pub async fn get(
     _uid: UserId,
     connection: DbConn,
     id: i32,
+) -> Result<Json<RenderedAccount>, Status> {
+    connection.run(move |c| Ok(Json(render(c, id)?))).await
+}
+
+#[post("/accounts", data = "<account>")]
+pub async fn create(
+    uid: UserId,
+    connection: DbConn,
+    account: Json<NewAccount>,
 ) -> Result<Json<RenderedAccount>, Status> {
     connection
         .run(move |c| {
-            let (account, syncing) = queries::account_by_id(c, id)
-                .map_err(|e| log_error_and_500(Box::new(e)))?
-                .ok_or(Status::NotFound)?;
+            let mut acc = account.0;
+            acc.user_id = *uid;
+            acc.iban = validate(&acc.name, &acc.color, &acc.iban)?;
+
+            let acc: Account = diesel::insert_into(accounts::table)
+                .values(&acc)
+                .get_result(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+            info!("User {} created account {}", *uid, acc.id);
+
+            Ok(Json(render(c, acc.id)?))
+        })
+        .await
+}
+
+#[put("/accounts/<id>", data = "<account>")]
+pub async fn update(
+    uid: UserId,
+    connection: DbConn,
+    id: i32,
+    account: Json<Account>,
+) -> Result<Json<RenderedAccount>, Status> {
+    connection
+        .run(move |c| {
+            own_account(c, *uid, id)?;
+
+            let mut acc = account.0;
+            acc.id = id;
+            acc.user_id = *uid;
+            acc.iban = validate(&acc.name, &acc.color, &acc.iban)?;
+
+            diesel::update(accounts::table.filter(accounts::id.eq(id)))
+                .set(&acc)
+                .execute(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+            info!("User {} updated account {}", *uid, id);
+
+            Ok(Json(render(c, id)?))
+        })
+        .await
+}
+
+#[put("/accounts/<id>/hidden", data = "<hidden>")]
+pub async fn set_hidden(
+    uid: UserId,
+    connection: DbConn,
+    id: i32,
+    hidden: Json<bool>,
+) -> Result<Json<RenderedAccount>, Status> {
+    connection
+        .run(move |c| {
+            own_account(c, *uid, id)?;
+
+            diesel::update(accounts::table.filter(accounts::id.eq(id)))
+                .set(accounts::hidden.eq(hidden.0))
+                .execute(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+            Ok(Json(render(c, id)?))
+        })
+        .await
+}
+
+// accounts that are still in use can only be deleted if it is specified what should happen to their transactions and balances:
+// `strategy=cascade` deletes them as well, `strategy=reassign&target=<id>` moves them to another account of the user
+#[delete("/accounts/<id>?<strategy>&<target>")]
+pub async fn delete(
+    uid: UserId,
+    connection: DbConn,
+    id: i32,
+    strategy: Option<String>,
+    target: Option<i32>,
+) -> Result<(), Status> {
+    connection
+        .run(move |c| {
+            own_account(c, *uid, id)?;
+
+            let reassign_to = match (strategy.as_deref(), target) {
+                (None, _) => {
+                    let transaction_count = expense_transactions::table
+                        .select(diesel::dsl::count(expense_transactions::id))
+                        .filter(expense_transactions::account_id.eq(id))
+                        .get_result::<i64>(c)
+                        .map_err(|e| log_error_and_500(Box::new(e)))?;
+                    let balance_count = balances::table
+                        .select(diesel::dsl::count(balances::id))
+                        .filter(balances::account_id.eq(id))
+                        .get_result::<i64>(c)
+                        .map_err(|e| log_error_and_500(Box::new(e)))?;
 
-            Ok(Json(RenderedAccount {
-                info: account,
-                synchronization: syncing,
-            }))
+                    if transaction_count > 0 || balance_count > 0 {
+                        warn!(
+                            "Refusing to delete account {} with {} transactions and {} balances",
+                            id, transaction_count, balance_count
+                        );
+                        return Err(Status::Conflict);
+                    }
+
+                    None
+                }
+                (Some("cascade"), None) => None,
+                (Some("reassign"), Some(target)) if target != id => {
+                    own_account(c, *uid, target).map_err(|_| Status::BadRequest)?;
+
+                    // balances are unique per account and date
+                    let dates = balances::table
+                        .filter(balances::account_id.eq(id))
+                        .select(balances::date)
+                        .load::<DateTime<Utc>>(c)
+                        .map_err(|e| log_error_and_500(Box::new(e)))?;
+                    let clashes = balances::table
+                        .select(diesel::dsl::count(balances::id))
+                        .filter(balances::account_id.eq(target))
+                        .filter(balances::date.eq_any(dates))
+                        .get_result::<i64>(c)
+                        .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+                    if clashes > 0 {
+                        warn!(
+                            "Cannot move balances of account {} to account {}: {} dates are used by both",
+                            id, target, clashes
+                        );
+                        return Err(Status::Conflict);
+                    }
+
+                    Some(target)
+                }
+                _ => return Err(Status::BadRequest),
+            };
+
+            mutations::delete_account(c, *uid, TOOL, id, reassign_to)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+            info!("User {} deleted account {}", *uid, id);
+
+            Ok(())
         })
         .await
 }
 
-// #[delete("/accounts/<id>")]
-// pub fn delete(uid: UserId, connection: DbConn, id: i32) -> Result<(), Status> {
-//     // let row_count = diesel::delete(
-//     //     accounts::table
-//     //         .filter(accounts::user_id.eq(*uid))
-//     //         .filter(accounts::id.eq(id)),
-//     // )
-//     // .execute(&*connection)
-//     // .map_err(|e| log_error_and_500(Box::new(e)))?;
-
-//     // if row_count == 0 {
-//     //     Err(Status::NotFound)
-//     // } else {
-//     //     info!("Deleted record {} from the account table", id);
-//     //     Ok(())
-//     // }
-
-//     todo!() // TODO
-// }
-
-// #[put("/accounts/<id>", data = "<account>")]
-// pub fn update(
-//     uid: UserId,
-//     connection: DbConn,
-//     id: i32,
-//     account: Json<Account>,
-// ) -> Result<(), Status> {
-//     // let mut acc = account.0;
-//     // acc.user_id = *uid;
-
-//     // let row_count = diesel::update(
-//     //     accounts::table
-//     //         .filter(accounts::id.eq(id))
-//     //         .filter(accounts::user_id.eq(*uid)),
-//     // )
-//     // .set(acc)
-//     // .execute(&*connection)
-//     // .map_err(|e| log_error_and_500(Box::new(e)))?;
-
-//     // if row_count == 0 {
-//     //     Err(Status::NotFound)
-//     // } else {
-//     //     info!("updated record {} from the account table", id);
-//     //     Ok(())
-//     // }
-
-//     todo!() // TODO
-// }
-
-// #[post("/accounts", data = "<account>")]
-// pub fn create(
-//     uid: UserId,
-//     connection: DbConn,
-//     account: Json<NewAccount>,
-// ) -> Result<Json<Account>, Status> {
-//     // let mut acc = account.0;
-//     // acc.user_id = *uid;
-
-//     // let t: Account = diesel::insert_into(crate::schema::accounts::table)
-//     //     .values(&acc)
-//     //     .get_result(&*connection)
-//     //     .map_err(|e| log_error_and_500(Box::new(e)))?;
-
-//     // Ok(Json(t))
-
-//     todo!() // TODO
-// }
+// balance of the account over time (as seen by its owner), computed from the balances and transactions.
+// defaults to daily values of the last year.
+#[get("/accounts/<id>/history?<from>&<to>&<resolution>")]
+pub async fn history(
+    uid: UserId,
+    connection: DbConn,
+    id: i32,
+    from: Option<String>,
+    to: Option<String>,
+    resolution: Option<String>,
+) -> Result<Json<Vec<DataPoint>>, Status> {
+    let dates = time_steps(from, to, resolution)?;
+
+    connection
+        .run(move |c| {
+            own_account(c, *uid, id)?;
+
+            let flows =
+                queries::account_flows(c, *uid, id).map_err(|e| log_error_and_500(Box::new(e)))?;
+            let checkpoints =
+                queries::account_checkpoints(c, id).map_err(|e| log_error_and_500(Box::new(e)))?;
+
+            Ok(Json(reports::balance_history(&checkpoints, &flows, &dates)))
+        })
+        .await
+}
@@ -1,20 +1,54 @@
 use crate::models::*;
 use crate::queries;
 use crate::rendering::RenderedBalance;
+use crate::reports::{self, Discrepancy};
 use crate::schema::*;
 use crate::web::pagination::*;
 use crate::web::user::UserId;
-use crate::web::util::{log_error_and_500, InfoResponse, QueryRequest, QueryResponse};
+use crate::web::util::{
+    log_error_and_409_or_500, log_error_and_500, InfoResponse, QueryRequest, QueryResponse,
+};
 use crate::web::DbConn;
 
 use diesel::prelude::*;
-use log::warn;
+use log::{info, warn};
 use rocket::http::Status;
 use rocket_contrib::databases::diesel;
 use rocket_contrib::json::Json;
 
 use std::collections::HashMap;
 
+// checks that the user is allowed to write the balance and converts it into the form in which it is stored
+fn unrender(c: &PgConnection, uid: i32, balance: NewBalance) -> Result<NewBalance, Status> {
+    let owner = accounts::table
+        .find(balance.account_id)
+        .select(accounts::user_id)
+        .get_result::<i32>(c)
+        .optional()
+        .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+    if owner != Some(uid) {
+        warn!(
+            "User {} tried to write a balance of account {}",
+            uid, balance.account_id
+        );
+        return Err(Status::BadRequest);
+    }
+
+    let sync = queries::synchronization_by_account_id(c, balance.account_id)
+        .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+    Ok(balance.unrender(sync.as_ref()))
+}
+
+fn render(c: &PgConnection, uid: i32, id: i32) -> Result<RenderedBalance, Status> {
+    let (balance, syncing) = queries::relevant_balance_by_id(c, uid, id)
+        .map_err(|e| log_error_and_500(Box::new(e)))?
+        .ok_or(Status::NotFound)?;
+
+    Ok(RenderedBalance::render(balance, syncing))
+}
+
 #[post("/balances/query", data = "<request>")]
 pub async fn query(
     uid: UserId,
@@ -227,6 +261,47 @@ pub async fn info(uid: UserId, connection: DbConn) -> Result<Json<InfoResponse>,
         .await
 }
 
+// intervals between consecutive balances of the user's accounts in which the balance changed by a different amount
+// than the expenses add up to, i.e. where expenses are probably missing
+#[get("/balances/reconciliation?<account>")]
+pub async fn reconciliation(
+    uid: UserId,
+    connection: DbConn,
+    account: Option<i32>,
+) -> Result<Json<Vec<Discrepancy>>, Status> {
+    connection
+        .run(move |c| {
+            let mut query = accounts::table
+                .filter(accounts::user_id.eq(*uid))
+                .select(accounts::id)
+                .order(accounts::id)
+                .into_boxed();
+            if let Some(account) = account {
+                query = query.filter(accounts::id.eq(account));
+            }
+            let ids = query
+                .load::<i32>(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+            if account.is_some() && ids.is_empty() {
+                return Err(Status::NotFound);
+            }
+
+            let mut discrepancies = Vec::new();
+            for id in ids.into_iter() {
+                let flows = queries::account_flows(c, *uid, id)
+                    .map_err(|e| log_error_and_500(Box::new(e)))?;
+                let checkpoints = queries::account_checkpoints(c, id)
+                    .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+                discrepancies.extend(reports::reconcile(id, &checkpoints, &flows));
+            }
+
+            Ok(Json(discrepancies))
+        })
+        .await
+}
+
 #[get("/balances?<offset>&<count>")]
 pub async fn list(
     uid: UserId,
@@ -252,38 +327,114 @@ pub async fn get(
     uid: UserId,
     connection: DbConn,
     id: i32,
+) -> Result<Json<RenderedBalance>, Status> {
+    connection
+        .run(move |c| Ok(Json(render(c, *uid, id)?)))
+        .await
+}
+
+#[post("/balances", data = "<balance>")]
+pub async fn create(
+    uid: UserId,
+    connection: DbConn,
+    balance: Json<NewBalance>,
 ) -> Result<Json<RenderedBalance>, Status> {
     connection
         .run(move |c| {
-            let (balance, syncing) = queries::relevant_balance_by_id(c, *uid, id)
-                .map_err(|e| log_error_and_500(Box::new(e)))?
-                .ok_or(Status::NotFound)?;
+            let balance = unrender(c, *uid, balance.0)?;
 
-            Ok(Json(RenderedBalance::render(balance, syncing)))
+            let b: Balance = diesel::insert_into(balances::table)
+                .values(&balance)
+                .get_result(c)
+                .map_err(log_error_and_409_or_500)?;
+            info!("User {} created balance {}", *uid, b.id);
+
+            Ok(Json(render(c, *uid, b.id)?))
         })
         .await
 }
 
-// #[delete("/balances/<id>")]
-// pub fn delete(uid: UserId, connection: DbConn, id: i32) -> Result<(), Status> {
-//     todo!() // TODO
-// }
-
-// #[put("/balances/<id>", data = "<balances>")]
-// pub fn update(
-//     uid: UserId,
-//     connection: DbConn,
-//     id: i32,
-//     account: Json<RenderedBalance>,
-// ) -> Result<(), Status> {
-//     todo!() // TODO
-// }
-
-// #[post("/balances", data = "<balances>")]
-// pub fn create(
-//     uid: UserId,
-//     connection: DbConn,
-//     account: Json<NewBalance>,
-// ) -> Result<Json<RenderedBalance>, Status> {
-//     todo!() // TODO
-// }
+// e.g. all balances of a month's statements at once. Either all of them are created or none.
+#[post("/balances/bulk", data = "<balances>")]
+pub async fn create_bulk(
+    uid: UserId,
+    connection: DbConn,
+    balances: Json<Vec<NewBalance>>,
+) -> Result<Json<Vec<RenderedBalance>>, Status> {
+    connection
+        .run(move |c| {
+            let balances = balances
+                .0
+                .into_iter()
+                .map(|b| unrender(c, *uid, b))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let bs = diesel::insert_into(balances::table)
+                .values(&balances)
+                .get_results::<Balance>(c)
+                .map_err(log_error_and_409_or_500)?;
+            info!("User {} created {} balances", *uid, bs.len());
+
+            Ok(Json(
+                bs.into_iter()
+                    .map(|b| render(c, *uid, b.id))
+                    .collect::<Result<Vec<_>, _>>()?,
+            ))
+        })
+        .await
+}
+
+#[put("/balances/<id>", data = "<balance>")]
+pub async fn update(
+    uid: UserId,
+    connection: DbConn,
+    id: i32,
+    balance: Json<RenderedBalance>,
+) -> Result<Json<RenderedBalance>, Status> {
+    connection
+        .run(move |c| {
+            render(c, *uid, id)?;
+
+            let balance = balance.0;
+            let balance = unrender(
+                c,
+                *uid,
+                NewBalance {
+                    account_id: balance.account_id,
+                    date: balance.date,
+                    amount: balance.amount,
+                    comment: balance.comment,
+                },
+            )?;
+
+            diesel::update(balances::table.find(id))
+                .set((
+                    balances::account_id.eq(balance.account_id),
+                    balances::date.eq(balance.date),
+                    balances::amount.eq(balance.amount),
+                    balances::comment.eq(balance.comment),
+                ))
+                .execute(c)
+                .map_err(log_error_and_409_or_500)?;
+            info!("User {} updated balance {}", *uid, id);
+
+            Ok(Json(render(c, *uid, id)?))
+        })
+        .await
+}
+
+#[delete("/balances/<id>")]
+pub async fn delete(uid: UserId, connection: DbConn, id: i32) -> Result<(), Status> {
+    connection
+        .run(move |c| {
+            render(c, *uid, id)?;
+
+            diesel::delete(balances::table.find(id))
+                .execute(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+            info!("User {} deleted balance {}", *uid, id);
+
+            Ok(())
+        })
+        .await
+}
@@ -0,0 +1,183 @@
+use crate::models::*;
+use crate::reports::{self, BudgetStatus};
+use crate::schema::*;
+use crate::web::reports::{categorized_expenses, category_parents, parse_view};
+use crate::web::user::UserId;
+use crate::web::util::{log_error_and_409_or_500, log_error_and_500};
+use crate::web::DbConn;
+
+use chrono::Utc;
+use diesel::prelude::*;
+use log::{info, warn};
+use rocket::http::Status;
+use rocket_contrib::databases::diesel;
+use rocket_contrib::json::Json;
+
+// budgets can only be set for the user's own categories
+fn validate(c: &PgConnection, uid: i32, category_id: i32, amount: i64) -> Result<(), Status> {
+    if amount < 0 {
+        warn!("Budget with negative amount {}", amount);
+        return Err(Status::BadRequest);
+    }
+
+    let owner = categories::table
+        .find(category_id)
+        .select(categories::user_id)
+        .get_result::<i32>(c)
+        .optional()
+        .map_err(|e| log_error_and_500(Box::new(e)))?;
+    if owner != Some(uid) {
+        warn!("Budget with unknown category {}", category_id);
+        return Err(Status::BadRequest);
+    }
+
+    Ok(())
+}
+
+fn own_budget(c: &PgConnection, uid: i32, id: i32) -> Result<Budget, Status> {
+    budgets::table
+        .filter(budgets::id.eq(id))
+        .filter(budgets::user_id.eq(uid))
+        .get_result::<Budget>(c)
+        .optional()
+        .map_err(|e| log_error_and_500(Box::new(e)))?
+        .ok_or(Status::NotFound)
+}
+
+#[get("/budgets")]
+pub async fn list(uid: UserId, connection: DbConn) -> Result<Json<Vec<Budget>>, Status> {
+    connection
+        .run(move |c| {
+            Ok(Json(
+                budgets::table
+                    .filter(budgets::user_id.eq(*uid))
+                    .order(budgets::id)
+                    .load::<Budget>(c)
+                    .map_err(|e| log_error_and_500(Box::new(e)))?,
+            ))
+        })
+        .await
+}
+
+#[get("/budgets/<id>")]
+pub async fn get(uid: UserId, connection: DbConn, id: i32) -> Result<Json<Budget>, Status> {
+    connection
+        .run(move |c| Ok(Json(own_budget(c, *uid, id)?)))
+        .await
+}
+
+#[post("/budgets", data = "<budget>")]
+pub async fn create(
+    uid: UserId,
+    connection: DbConn,
+    budget: Json<NewBudget>,
+) -> Result<Json<Budget>, Status> {
+    connection
+        .run(move |c| {
+            let mut budget = budget.0;
+            budget.user_id = *uid;
+            validate(c, *uid, budget.category_id, budget.amount)?;
+
+            let budget: Budget = diesel::insert_into(budgets::table)
+                .values(&budget)
+                .get_result(c)
+                .map_err(log_error_and_409_or_500)?;
+            info!("User {} created budget {}", *uid, budget.id);
+
+            Ok(Json(budget))
+        })
+        .await
+}
+
+#[put("/budgets/<id>", data = "<budget>")]
+pub async fn update(
+    uid: UserId,
+    connection: DbConn,
+    id: i32,
+    budget: Json<Budget>,
+) -> Result<Json<Budget>, Status> {
+    connection
+        .run(move |c| {
+            own_budget(c, *uid, id)?;
+
+            let mut budget = budget.0;
+            budget.id = id;
+            budget.user_id = *uid;
+            validate(c, *uid, budget.category_id, budget.amount)?;
+
+            let budget = diesel::update(&budget)
+                .set(&budget)
+                .get_result::<Budget>(c)
+                .map_err(log_error_and_409_or_500)?;
+            info!("User {} updated budget {}", *uid, id);
+
+            Ok(Json(budget))
+        })
+        .await
+}
+
+#[delete("/budgets/<id>")]
+pub async fn delete(uid: UserId, connection: DbConn, id: i32) -> Result<(), Status> {
+    connection
+        .run(move |c| {
+            let budget = own_budget(c, *uid, id)?;
+
+            diesel::delete(&budget)
+                .execute(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+            info!("User {} deleted budget {}", *uid, id);
+
+            Ok(())
+        })
+        .await
+}
+
+// spending in the current period of each budget compared to its limit
+#[get("/budgets/status?<view>")]
+pub async fn status(
+    uid: UserId,
+    connection: DbConn,
+    view: Option<String>,
+) -> Result<Json<Vec<BudgetStatus>>, Status> {
+    let view = parse_view(view)?;
+
+    connection
+        .run(move |c| {
+            let budgets = budgets::table
+                .filter(budgets::user_id.eq(*uid))
+                .order(budgets::id)
+                .load::<Budget>(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+            let now = Utc::now();
+
+            // everything since the earliest period that is needed for the rollovers
+            let from = budgets
+                .iter()
+                .map(|b| {
+                    let date = if b.rollover { b.start.min(now) } else { now };
+                    reports::budget_period(b.period, date).0
+                })
+                .min();
+            let from = match from {
+                Some(from) => from,
+                None => return Ok(Json(Vec::new())),
+            };
+            let to = budgets
+                .iter()
+                .map(|b| reports::budget_period(b.period, now).1)
+                .max()
+                .unwrap_or(now);
+
+            let exps = categorized_expenses(c, *uid, from, to, view)?;
+            let parents = category_parents(c, *uid, &exps)?;
+
+            Ok(Json(
+                budgets
+                    .iter()
+                    .map(|b| reports::budget_status(b, &exps, &parents, now))
+                    .collect(),
+            ))
+        })
+        .await
+}
@@ -1,15 +1,104 @@
 use crate::models::*;
+use crate::mutations;
 use crate::rendering::RenderedCategory;
 use crate::schema::*;
 use crate::web::user::UserId;
 use crate::web::DbConn;
 
-use crate::web::util::log_error_and_500;
+use crate::web::util::{is_valid_color, log_error_and_500, TOOL};
 use diesel::prelude::*;
+use log::{info, warn};
 use rocket::http::Status;
 use rocket_contrib::databases::diesel;
 use rocket_contrib::json::Json;
 
+use std::collections::HashMap;
+
+// parents of all categories of a user
+fn category_tree(c: &PgConnection, uid: i32) -> Result<HashMap<i32, Option<i32>>, Status> {
+    Ok(categories::table
+        .filter(categories::user_id.eq(uid))
+        .select((categories::id, categories::parent))
+        .load::<(i32, Option<i32>)>(c)
+        .map_err(|e| log_error_and_500(Box::new(e)))?
+        .into_iter()
+        .collect())
+}
+
+// whether `id` is `root` itself or one of its (transitive) children
+fn is_in_subtree(tree: &HashMap<i32, Option<i32>>, id: i32, root: i32) -> bool {
+    let mut current = Some(id);
+
+    // the number of steps is bounded in case there already is a cycle
+    for _ in 0..=tree.len() {
+        match current {
+            Some(x) if x == root => return true,
+            Some(x) => current = tree.get(&x).cloned().flatten(),
+            None => return false,
+        }
+    }
+
+    false
+}
+
+fn validate(
+    tree: &HashMap<i32, Option<i32>>,
+    id: Option<i32>,
+    name: &str,
+    color: &Option<String>,
+    parent: Option<i32>,
+) -> Result<(), Status> {
+    if name.trim().is_empty() {
+        warn!("Category without name");
+        return Err(Status::BadRequest);
+    }
+
+    if let Some(color) = color {
+        if !is_valid_color(color) {
+            warn!("Category with invalid color '{}'", color);
+            return Err(Status::BadRequest);
+        }
+    }
+
+    if let Some(parent) = parent {
+        if !tree.contains_key(&parent) {
+            warn!("Category with unknown parent {}", parent);
+            return Err(Status::BadRequest);
+        }
+
+        if let Some(id) = id {
+            if is_in_subtree(tree, parent, id) {
+                warn!(
+                    "Making {} the parent of category {} would create a cycle",
+                    parent, id
+                );
+                return Err(Status::BadRequest);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn render(c: &PgConnection, id: i32) -> Result<RenderedCategory, Status> {
+    let cs = categories::table
+        .filter(categories::id.eq(id))
+        .left_join(
+            category_replacements::table.on(categories::id.eq(category_replacements::original)),
+        )
+        .load::<(Category, Option<CategoryReplacement>)>(c)
+        .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+    if let Some((cat, _)) = cs.get(0) {
+        let cat = cat.clone();
+        let repl = cs.into_iter().filter_map(|(_, r)| r).collect::<Vec<_>>();
+
+        Ok(RenderedCategory::render(cat, &repl))
+    } else {
+        Err(Status::NotFound)
+    }
+}
+
 #[get("/categories")]
 pub async fn list(_uid: UserId, connection: DbConn) -> Result<Json<Vec<RenderedCategory>>, Status> {
     connection
@@ -35,50 +124,154 @@ pub async fn get(
     _uid: UserId,
     connection: DbConn,
     id: i32,
+) -> Result<Json<RenderedCategory>, Status> {
+    connection.run(move |c| Ok(Json(render(c, id)?))).await
+}
+
+#[post("/categories", data = "<category>")]
+pub async fn create(
+    uid: UserId,
+    connection: DbConn,
+    category: Json<NewCategory>,
 ) -> Result<Json<RenderedCategory>, Status> {
     connection
         .run(move |c| {
-            let cs = categories::table
-                .filter(categories::id.eq(id))
-                .left_join(
-                    category_replacements::table
-                        .on(categories::id.eq(category_replacements::original)),
-                )
-                .load::<(Category, Option<CategoryReplacement>)>(c)
+            let mut cat = category.0;
+            cat.user_id = *uid;
+
+            let tree = category_tree(c, *uid)?;
+            validate(&tree, None, &cat.name, &cat.color, cat.parent)?;
+
+            let cat: Category = diesel::insert_into(categories::table)
+                .values(&cat)
+                .get_result(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+            info!("User {} created category {}", *uid, cat.id);
+
+            Ok(Json(render(c, cat.id)?))
+        })
+        .await
+}
+
+#[put("/categories/<id>", data = "<category>")]
+pub async fn update(
+    uid: UserId,
+    connection: DbConn,
+    id: i32,
+    category: Json<Category>,
+) -> Result<Json<RenderedCategory>, Status> {
+    connection
+        .run(move |c| {
+            let tree = category_tree(c, *uid)?;
+            if !tree.contains_key(&id) {
+                return Err(Status::NotFound);
+            }
+
+            let mut cat = category.0;
+            cat.id = id;
+            cat.user_id = *uid;
+            validate(&tree, Some(id), &cat.name, &cat.color, cat.parent)?;
+
+            diesel::update(categories::table.filter(categories::id.eq(id)))
+                .set(&cat)
+                .execute(c)
                 .map_err(|e| log_error_and_500(Box::new(e)))?;
+            info!("User {} updated category {}", *uid, id);
 
-            if let Some((cat, _)) = cs.get(0) {
-                let cat = cat.clone();
-                let repl = cs.into_iter().filter_map(|(_, r)| r).collect::<Vec<_>>();
+            Ok(Json(render(c, id)?))
+        })
+        .await
+}
 
-                Ok(Json(RenderedCategory::render(cat, &repl)))
-            } else {
-                Err(Status::NotFound)
+// moves all expenses, replacements and children of a category to `target` and removes it afterwards
+#[post("/categories/<id>/merge/<target>")]
+pub async fn merge(
+    uid: UserId,
+    connection: DbConn,
+    id: i32,
+    target: i32,
+) -> Result<Json<RenderedCategory>, Status> {
+    connection
+        .run(move |c| {
+            let tree = category_tree(c, *uid)?;
+            if !tree.contains_key(&id) {
+                return Err(Status::NotFound);
+            }
+            if !tree.contains_key(&target) || is_in_subtree(&tree, target, id) {
+                warn!("Cannot merge category {} into {}", id, target);
+                return Err(Status::BadRequest);
             }
+
+            mutations::merge_category(c, *uid, TOOL, id, target)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+            info!("User {} merged category {} into {}", *uid, id, target);
+
+            Ok(Json(render(c, target)?))
         })
         .await
 }
 
-// #[delete("/balances/<id>")]
-// pub fn delete(uid: UserId, connection: DbConn, id: i32) -> Result<(), Status> {
-//     todo!() // TODO
-// }
-
-// #[put("/balances/<id>", data = "<balances>")]
-// pub fn update(
-//     uid: UserId,
-//     connection: DbConn,
-//     id: i32,
-//     account: Json<RenderedBalance>,
-// ) -> Result<(), Status> {
-//     todo!() // TODO
-// }
-
-// #[post("/balances", data = "<balances>")]
-// pub fn create(
-//     uid: UserId,
-//     connection: DbConn,
-//     account: Json<NewBalance>,
-// ) -> Result<Json<RenderedBalance>, Status> {
-//     todo!() // TODO
-// }
+// categories that have children or are used by expenses can only be deleted if it is specified what should happen to them:
+// `strategy=cascade` deletes all children as well (expenses just lose these categories),
+// `strategy=reassign&target=<id>` merges the category into another one (see `merge`)
+#[delete("/categories/<id>?<strategy>&<target>")]
+pub async fn delete(
+    uid: UserId,
+    connection: DbConn,
+    id: i32,
+    strategy: Option<String>,
+    target: Option<i32>,
+) -> Result<(), Status> {
+    connection
+        .run(move |c| {
+            let tree = category_tree(c, *uid)?;
+            if !tree.contains_key(&id) {
+                return Err(Status::NotFound);
+            }
+
+            let result = match (strategy.as_deref(), target) {
+                (None, _) => {
+                    let child_count = tree.values().filter(|p| **p == Some(id)).count();
+                    let expense_count = expense_categories::table
+                        .select(diesel::dsl::count(expense_categories::expense_id))
+                        .filter(expense_categories::category_id.eq(id))
+                        .get_result::<i64>(c)
+                        .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+                    if child_count > 0 || expense_count > 0 {
+                        warn!(
+                            "Refusing to delete category {} with {} children and {} expenses",
+                            id, child_count, expense_count
+                        );
+                        return Err(Status::Conflict);
+                    }
+
+                    mutations::delete_categories(c, *uid, TOOL, vec![id])
+                }
+                (Some("cascade"), None) => {
+                    let ids = tree
+                        .keys()
+                        .cloned()
+                        .filter(|x| is_in_subtree(&tree, *x, id))
+                        .collect::<Vec<_>>();
+
+                    mutations::delete_categories(c, *uid, TOOL, ids)
+                }
+                (Some("reassign"), Some(target)) => {
+                    if !tree.contains_key(&target) || is_in_subtree(&tree, target, id) {
+                        warn!("Cannot merge category {} into {}", id, target);
+                        return Err(Status::BadRequest);
+                    }
+
+                    mutations::merge_category(c, *uid, TOOL, id, target)
+                }
+                _ => return Err(Status::BadRequest),
+            };
+
+            result.map_err(|e| log_error_and_500(Box::new(e)))?;
+            info!("User {} deleted category {}", *uid, id);
+
+            Ok(())
+        })
+        .await
+}
@@ -1,23 +1,640 @@
 use crate::models::*;
+use crate::mutations;
 use crate::queries;
+use crate::receipts::ReceiptStore;
 use crate::rendering::RenderedExpense;
 use crate::schema::*;
 use crate::web::pagination::*;
 use crate::web::user::UserId;
-use crate::web::util::{log_error_and_500, InfoResponse, QueryRequest, QueryResponse};
+use crate::web::util::{log_error_and_500, InfoResponse, QueryRequest, QueryResponse, TOOL};
 use crate::web::DbConn;
 
-use chrono::{Duration, Utc};
+use chrono::{DateTime, Duration, Utc};
 use diesel::dsl::sql;
 use diesel::prelude::*;
-use diesel::sql_types::BigInt;
-use log::warn;
+use diesel::sql_types::{BigInt, Bool, Float};
+use log::{info, warn};
 use rocket::http::Status;
+use rocket::State;
 use rocket_contrib::databases::diesel;
 use rocket_contrib::json::Json;
+use serde::{Deserialize, Serialize};
 
 use std::collections::HashMap;
 
+// An expense as it is sent by the client, i.e. shaped like `RenderedExpense` without the computed parts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExpenseRequest {
+    pub info: NewExpense,
+    pub transactions: Vec<TransactionRequest>,
+    pub categories: Vec<CategoryRequest>,
+    #[serde(default)]
+    pub receipts: Option<Vec<ReceiptRequest>>, // only used to remove receipts when updating, `None` leaves them untouched
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransactionRequest {
+    pub id: Option<i32>, // only used when updating expenses
+    pub account_id: i32,
+    pub date: DateTime<Utc>,
+    pub amount: Option<i64>,
+    pub fraction: Option<f64>,
+    #[serde(default)]
+    pub comments: String,
+    #[serde(default)]
+    pub statement: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CategoryRequest {
+    pub category_id: i32,
+    pub weight: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ReceiptRequest {
+    pub id: Option<i32>,
+    pub file_name: String,
+}
+
+// larger bulk changes have to list the `ids` of the expenses explicitly
+const BULK_QUERY_LIMIT: i64 = 1000;
+
+// changes to many expenses at once, which are selected either by `ids` or by the filters of `query` (paging is ignored)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkRequest {
+    pub ids: Option<Vec<i32>>,
+    pub query: Option<QueryRequest>,
+    pub is_unchecked: Option<bool>,
+    pub is_tax_relevant: Option<bool>,
+    pub is_preliminary: Option<bool>,
+    pub categories: Option<Vec<CategoryRequest>>,
+    #[serde(default)]
+    pub replace_categories: bool, // otherwise `categories` are added (and the weights of existing ones are overwritten)
+    pub append_comments: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TemplateRequest {
+    pub date: DateTime<Utc>,
+    pub amount: Option<i64>, // replaces the amount of the template's only transaction with a fixed amount
+}
+
+impl TransactionRequest {
+    fn convert(&self, expense_id: i32) -> NewExpenseTransaction {
+        NewExpenseTransaction {
+            expense_id,
+            account_id: self.account_id,
+            date: self.date,
+            amount: self.amount,
+            fraction: self.fraction,
+            comments: self.comments.clone(),
+            statement: self.statement.clone(),
+        }
+    }
+}
+
+impl CategoryRequest {
+    fn convert(&self, expense_id: i32) -> ExpenseCategory {
+        ExpenseCategory {
+            expense_id,
+            category_id: self.category_id,
+            weight: self.weight,
+        }
+    }
+}
+
+// checks the constraints from `models.rs` that the database does not enforce,
+// and that the user is allowed to use the referenced accounts and categories
+// (when updating, everything that is already used by the `current` expense is allowed as well)
+fn validate(
+    c: &PgConnection,
+    uid: i32,
+    request: &ExpenseRequest,
+    current: Option<&RenderedExpense>,
+) -> Result<(), Status> {
+    if request.info.booking_start > request.info.booking_end {
+        warn!("Expense with booking start after booking end");
+        return Err(Status::BadRequest);
+    }
+
+    if request
+        .transactions
+        .iter()
+        .any(|t| t.amount.is_some() == t.fraction.is_some())
+    {
+        warn!("Expense with transactions that do not have exactly one of amount and fraction");
+        return Err(Status::BadRequest);
+    }
+
+    let num_amounts = request
+        .transactions
+        .iter()
+        .filter(|t| t.amount.is_some())
+        .count();
+    if num_amounts == 0 {
+        warn!("Expense without transactions with a fixed amount");
+        return Err(Status::BadRequest);
+    }
+    if num_amounts > 1 && num_amounts < request.transactions.len() {
+        warn!("Expense with fractional transactions and multiple fixed amounts");
+        return Err(Status::BadRequest);
+    }
+
+    let mut accounts =
+        queries::writable_account_ids(c, uid).map_err(|e| log_error_and_500(Box::new(e)))?;
+    if let Some(current) = current {
+        accounts.extend(current.transactions.iter().map(|t| t.account_id));
+    }
+    if let Some(t) = request
+        .transactions
+        .iter()
+        .find(|t| !accounts.contains(&t.account_id))
+    {
+        warn!(
+            "User {} tried to book a transaction on account {}",
+            uid, t.account_id
+        );
+        return Err(Status::BadRequest);
+    }
+
+    validate_categories(c, uid, &request.categories, current)
+}
+
+fn validate_categories(
+    c: &PgConnection,
+    uid: i32,
+    categories: &[CategoryRequest],
+    current: Option<&RenderedExpense>,
+) -> Result<(), Status> {
+    if categories
+        .iter()
+        .any(|ec| !ec.weight.is_finite() || ec.weight <= 0.0)
+    {
+        warn!("Expense with non-positive category weights");
+        return Err(Status::BadRequest);
+    }
+
+    let mut category_ids = categories
+        .iter()
+        .map(|ec| ec.category_id)
+        .collect::<Vec<_>>();
+    category_ids.sort_unstable();
+    category_ids.dedup();
+    if category_ids.len() != categories.len() {
+        warn!("Expense with duplicate categories");
+        return Err(Status::BadRequest);
+    }
+
+    if let Some(current) = current {
+        category_ids.retain(|id| !current.categories.iter().any(|ec| ec.category_id == *id));
+    }
+
+    let own_categories = categories::table
+        .select(diesel::dsl::count(categories::id))
+        .filter(categories::id.eq_any(category_ids.clone()))
+        .filter(categories::user_id.eq(uid))
+        .get_result::<i64>(c)
+        .map_err(|e| log_error_and_500(Box::new(e)))?;
+    if own_categories != category_ids.len() as i64 {
+        warn!("User {} tried to use categories that are not theirs", uid);
+        return Err(Status::BadRequest);
+    }
+
+    Ok(())
+}
+
+fn render(c: &PgConnection, uid: i32, id: i32) -> Result<RenderedExpense, Status> {
+    let exp = queries::relevant_expense_by_id(c, uid, id)
+        .map_err(|e| log_error_and_500(Box::new(e)))?
+        .ok_or(Status::NotFound)?;
+
+    let transactions = queries::expense_transactions_by_expense_id(c, uid, id)
+        .map_err(|e| log_error_and_500(Box::new(e)))?;
+    let categories = queries::expense_categories_by_expense_id(c, uid, id)
+        .map_err(|e| log_error_and_500(Box::new(e)))?;
+    let receipts = expense_receipts::table
+        .filter(expense_receipts::expense_id.eq(id))
+        .load::<ExpenseReceipt>(c)
+        .map_err(|e| log_error_and_500(Box::new(e)))?;
+    let events = expense_events::table
+        .filter(expense_events::expense_id.eq(id))
+        .load::<ExpenseEvent>(c)
+        .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+    Ok(RenderedExpense::render(
+        uid,
+        exp,
+        transactions,
+        categories,
+        receipts,
+        events,
+    ))
+}
+
+// renders multiple expenses at once (with much less queries than calling `render` for each of them)
+fn render_all(
+    c: &PgConnection,
+    uid: i32,
+    expenses: Vec<Expense>,
+) -> Result<Vec<RenderedExpense>, Status> {
+    queries::render_expenses(c, uid, expenses).map_err(|e| log_error_and_500(Box::new(e)))
+}
+
+// `min..max` (both optional and inclusive) or a single amount, parsed into numbers so that they can be inlined into sql
+fn parse_amount_range(s: &str) -> Option<(Option<i64>, Option<i64>)> {
+    let bound = |b: &str| {
+        let b = b.trim();
+        if b.is_empty() {
+            Some(None)
+        } else {
+            b.parse::<i64>().ok().map(Some)
+        }
+    };
+
+    match s.find("..") {
+        Some(i) => Some((bound(&s[..i])?, bound(&s[i + 2..])?)),
+        None => {
+            let amount = s.trim().parse::<i64>().ok()?;
+            Some((Some(amount), Some(amount)))
+        }
+    }
+}
+
+// a decimal amount with at most two fraction digits like "12", "12.5" or "12,50", in cents
+fn parse_cents(amount: &str) -> Option<i64> {
+    let (whole, fraction) = match amount.find(&['.', ','][..]) {
+        Some(i) => (&amount[..i], &amount[i + 1..]),
+        None => (amount, ""),
+    };
+    if (whole.is_empty() && fraction.is_empty())
+        || fraction.len() > 2
+        || !whole
+            .chars()
+            .chain(fraction.chars())
+            .all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+
+    let whole = if whole.is_empty() {
+        0
+    } else {
+        whole.parse::<i64>().ok()?
+    };
+    let fraction = format!("{:0<2}", fraction).parse::<i64>().ok()?;
+    whole.checked_mul(100)?.checked_add(fraction)
+}
+
+// splits a search into words for the full text search and conditions on the (absolute) total amount, which are
+// written like `=12.34`, `>100` or `<=5,50`. Returns `None` if an amount cannot be parsed.
+fn parse_needle(uid: i32, needle: &str) -> Option<(Vec<String>, Vec<String>)> {
+    let mut words = Vec::new();
+    let mut conditions = Vec::new();
+
+    for token in needle.split_whitespace() {
+        let op = [">=", "<=", "=", ">", "<"]
+            .iter()
+            .find(|op| token.starts_with(*op));
+
+        match op {
+            Some(op) => {
+                // totals are compared by their absolute value, so a sign is ignored
+                let amount = &token[op.len()..];
+                let amount = parse_cents(amount.strip_prefix('-').unwrap_or(amount))?;
+                conditions.push(format!(
+                    "ABS({}) {} {}",
+                    queries::total_amount_expression(uid),
+                    op,
+                    amount
+                ));
+            }
+            None => {
+                let word = token
+                    .chars()
+                    .filter(|c| c.is_alphanumeric() || *c == '.')
+                    .collect::<String>()
+                    .trim_matches('.')
+                    .to_lowercase();
+                if !word.is_empty() {
+                    words.push(word);
+                }
+            }
+        }
+    }
+
+    Some((words, conditions))
+}
+
+// the (non-deleted) expenses of the user that match the filters of the request, ordered and paginated as requested.
+// also returns the total number of matching expenses.
+fn query_expenses(
+    c: &PgConnection,
+    uid: i32,
+    request: &QueryRequest,
+) -> Result<(Vec<Expense>, i64), Status> {
+    let (words, amount_conditions) = match &request.needle {
+        Some(needle) => parse_needle(uid, needle).ok_or_else(|| {
+            warn!("Invalid search '{}'", needle);
+            Status::BadRequest
+        })?,
+        None => (Vec::new(), Vec::new()),
+    };
+
+    // words are reduced to letters, digits and dots by `parse_needle`, so they can be inlined into sql.
+    // like the total amount, the rank has to be part of the select list because it is used for ordering.
+    let tsquery = if words.is_empty() {
+        None
+    } else {
+        let terms = words.iter().map(|w| format!("{}:*", w)).collect::<Vec<_>>();
+        Some(format!("to_tsquery('simple', '{}')", terms.join(" & ")))
+    };
+    let rank = match &tsquery {
+        Some(tsquery) => format!(
+            "COALESCE((SELECT ts_rank(document, {}) FROM expense_search WHERE expense_id = expenses.id), 0)",
+            tsquery
+        ),
+        None => "0::REAL".into(),
+    };
+
+    let mut query = expenses::table
+        .select((
+            expenses::all_columns,
+            queries::total_amount_sql(uid),
+            sql::<Float>(&rank),
+        ))
+        .distinct()
+        .left_join(
+            expense_transactions::table.on(expense_transactions::expense_id.eq(expenses::id)),
+        )
+        .left_join(
+            account_synchronizations::table.on(expense_transactions::account_id
+                .eq(account_synchronizations::account1)
+                .or(expense_transactions::account_id.eq(account_synchronizations::account2))),
+        )
+        .left_join(accounts::table.on(accounts::id.eq(expense_transactions::account_id)))
+        .filter(
+            accounts::user_id
+                .eq(uid)
+                .or(account_synchronizations::user1.eq(uid))
+                .or(account_synchronizations::user2.eq(uid)),
+        )
+        .left_join(expense_categories::table.on(expense_categories::expense_id.eq(expenses::id)))
+        .left_join(
+            category_replacements::table.on(category_replacements::replacement
+                .eq(expense_categories::category_id)
+                .and(category_replacements::user_id.eq(uid))),
+        )
+        .filter(expenses::is_deleted.eq(false))
+        .into_boxed();
+
+    if let Some(from) = request.from {
+        query = query.filter(expenses::booking_end.ge(from));
+    }
+    if let Some(to) = request.to {
+        query = query.filter(expenses::booking_start.le(to));
+    }
+
+    query = query
+        .then_order_by(expenses::is_template.asc())
+        .then_order_by(expenses::is_unchecked.desc());
+
+    // when searching for words, the best matches come first and the requested order only breaks ties
+    if tsquery.is_some() {
+        query = query.then_order_by(sql::<Float>(&rank).desc());
+    }
+
+    for sb in request.sort_by.iter() {
+        let asc = sb.direction.to_lowercase() == "ascending";
+
+        match sb.column.as_ref() {
+            "totalAmount" | "total_amount" => {
+                if asc {
+                    query = query.then_order_by(queries::total_amount_sql(uid).asc());
+                } else {
+                    query = query.then_order_by(queries::total_amount_sql(uid).desc());
+                }
+            }
+            "info.title" => {
+                if asc {
+                    query = query.then_order_by(expenses::title.asc());
+                } else {
+                    query = query.then_order_by(expenses::title.desc());
+                }
+            }
+            "info.id" => {
+                if asc {
+                    query = query.then_order_by(expenses::id.asc());
+                } else {
+                    query = query.then_order_by(expenses::id.desc());
+                }
+            }
+            "info.store" => {
+                if asc {
+                    query = query.then_order_by(expenses::store.asc());
+                } else {
+                    query = query.then_order_by(expenses::store.desc());
+                }
+            }
+            "info.description" => {
+                if asc {
+                    query = query.then_order_by(expenses::description.asc());
+                } else {
+                    query = query.then_order_by(expenses::description.desc());
+                }
+            }
+            "info.comments" => {
+                if asc {
+                    query = query.then_order_by(expenses::comments.asc());
+                } else {
+                    query = query.then_order_by(expenses::comments.desc());
+                }
+            }
+            "info.bookingStart" | "info.booking_start" => {
+                if asc {
+                    query = query.then_order_by(expenses::booking_start.asc());
+                } else {
+                    query = query.then_order_by(expenses::booking_start.desc());
+                }
+            }
+            "info.bookingEnd" | "info.booking_end" => {
+                if asc {
+                    query = query.then_order_by(expenses::booking_end.asc());
+                } else {
+                    query = query.then_order_by(expenses::booking_end.desc());
+                }
+            }
+            "info.isTemplate" | "info.is_template" => {
+                if asc {
+                    query = query.then_order_by(expenses::is_template.asc());
+                } else {
+                    query = query.then_order_by(expenses::is_template.desc());
+                }
+            }
+            "info.isPreliminary" | "info.is_preliminary" => {
+                if asc {
+                    query = query.then_order_by(expenses::is_preliminary.asc());
+                } else {
+                    query = query.then_order_by(expenses::is_preliminary.desc());
+                }
+            }
+            "info.isTaxRelevant" | "info.is_tax_relevant" => {
+                if asc {
+                    query = query.then_order_by(expenses::is_tax_relevant.asc());
+                } else {
+                    query = query.then_order_by(expenses::is_tax_relevant.desc());
+                }
+            }
+            "info.isUnchecked" | "info.is_unchecked" => {
+                if asc {
+                    query = query.then_order_by(expenses::is_unchecked.asc());
+                } else {
+                    query = query.then_order_by(expenses::is_unchecked.desc());
+                }
+            }
+            _ => {
+                return Err(Status::BadRequest);
+            }
+        }
+    }
+
+    query = query.then_order_by(expenses::id.asc());
+
+    for (column, values) in request.filter_by.iter() {
+        match column.as_ref() {
+            "transactions" => {
+                let values_parsed = values
+                    .into_iter()
+                    .map(|s| s.parse().ok())
+                    .collect::<Option<Vec<i32>>>()
+                    .ok_or(Status::BadRequest)?;
+                query = query.filter(
+                    expense_transactions::account_id
+                        .eq_any(values_parsed.clone())
+                        .or(account_synchronizations::account1
+                            .eq_any(values_parsed.clone())
+                            .or(account_synchronizations::account2.eq_any(values_parsed))),
+                );
+            }
+            "categories" => {
+                let values_parsed = values
+                    .into_iter()
+                    .map(|s| s.parse().ok())
+                    .collect::<Option<Vec<i32>>>()
+                    .ok_or(Status::BadRequest)?;
+                query = query.filter(
+                    expense_categories::category_id
+                        .eq_any(values_parsed.clone())
+                        .or(category_replacements::original.eq_any(values_parsed.clone())),
+                );
+            }
+            "totalAmount" | "total_amount" => {
+                // ranges of cents like `-5000..-1000`, `..0` or `100..`, or exact amounts
+                let ranges = values
+                    .iter()
+                    .map(|v| parse_amount_range(v))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or(Status::BadRequest)?;
+                let amount = queries::total_amount_expression(uid);
+                let conditions = ranges
+                    .into_iter()
+                    .map(|(min, max)| match (min, max) {
+                        (Some(min), Some(max)) => {
+                            format!("({} BETWEEN {} AND {})", amount, min, max)
+                        }
+                        (Some(min), None) => format!("({} >= {})", amount, min),
+                        (None, Some(max)) => format!("({} <= {})", amount, max),
+                        (None, None) => "TRUE".into(),
+                    })
+                    .collect::<Vec<_>>();
+                if !conditions.is_empty() {
+                    query = query.filter(sql::<Bool>(&format!("({})", conditions.join(" OR "))));
+                }
+            }
+            "info.title" => {
+                query = query.filter(expenses::title.eq_any(values));
+            }
+            "info.id" => {
+                let values_parsed = values
+                    .into_iter()
+                    .map(|s| s.parse().ok())
+                    .collect::<Option<Vec<i32>>>()
+                    .ok_or(Status::BadRequest)?;
+
+                query = query.filter(expenses::id.eq_any(values_parsed));
+            }
+            "info.store" => {
+                query = query.filter(expenses::store.eq_any(values));
+            }
+            "info.description" => {
+                query = query.filter(expenses::description.eq_any(values));
+            }
+            "info.comments" => {
+                query = query.filter(expenses::comments.eq_any(values));
+            }
+            "info.isTemplate" | "info.is_template" => {
+                let values_parsed = values
+                    .into_iter()
+                    .map(|s| s.parse().ok())
+                    .collect::<Option<Vec<bool>>>()
+                    .ok_or(Status::BadRequest)?;
+                query = query.filter(expenses::is_template.eq_any(values_parsed));
+            }
+            "info.isPreliminary" | "info.is_preliminary" => {
+                let values_parsed = values
+                    .into_iter()
+                    .map(|s| s.parse().ok())
+                    .collect::<Option<Vec<bool>>>()
+                    .ok_or(Status::BadRequest)?;
+                query = query.filter(expenses::is_preliminary.eq_any(values_parsed));
+            }
+            "info.isTaxRelevant" | "info.is_tax_relevant" => {
+                let values_parsed = values
+                    .into_iter()
+                    .map(|s| s.parse().ok())
+                    .collect::<Option<Vec<bool>>>()
+                    .ok_or(Status::BadRequest)?;
+                query = query.filter(expenses::is_tax_relevant.eq_any(values_parsed));
+            }
+            "info.isUnchecked" | "info.is_unchecked" => {
+                let values_parsed = values
+                    .into_iter()
+                    .map(|s| s.parse().ok())
+                    .collect::<Option<Vec<bool>>>()
+                    .ok_or(Status::BadRequest)?;
+                query = query.filter(expenses::is_unchecked.eq_any(values_parsed));
+            }
+            _ => {
+                return Err(Status::BadRequest);
+            }
+        }
+    }
+
+    if let Some(tsquery) = &tsquery {
+        query = query.filter(sql::<Bool>(&format!(
+            "expenses.id IN (SELECT expense_id FROM expense_search WHERE document @@ {})",
+            tsquery
+        )));
+    }
+    for condition in amount_conditions.iter() {
+        query = query.filter(sql::<Bool>(condition));
+    }
+
+    let (exps, count) = query
+        .paginate(request.page)
+        .per_page(request.rows_per_page)
+        .load_and_count::<(Expense, i64, f32)>(c)
+        .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+    Ok((exps.into_iter().map(|(e, _, _)| e).collect(), count))
+}
+
 #[post("/expenses/query", data = "<request>")]
 pub async fn query(
     uid: UserId,
@@ -41,304 +658,9 @@ pub async fn query(
                 return Err(Status::BadRequest);
             }
 
-            let mut query = expenses::table
-                .select(expenses::all_columns)
-                .distinct()
-                .left_join(
-                    expense_transactions::table
-                        .on(expense_transactions::expense_id.eq(expenses::id)),
-                )
-                .left_join(
-                    account_synchronizations::table.on(expense_transactions::account_id
-                        .eq(account_synchronizations::account1)
-                        .or(
-                            expense_transactions::account_id.eq(account_synchronizations::account2)
-                        )),
-                )
-                .left_join(accounts::table.on(accounts::id.eq(expense_transactions::account_id)))
-                .filter(
-                    accounts::user_id
-                        .eq(*uid)
-                        .or(account_synchronizations::user1.eq(*uid))
-                        .or(account_synchronizations::user2.eq(*uid)),
-                )
-                .left_join(
-                    expense_categories::table.on(expense_categories::expense_id.eq(expenses::id)),
-                )
-                .left_join(
-                    category_replacements::table.on(category_replacements::replacement
-                        .eq(expense_categories::category_id)
-                        .and(category_replacements::user_id.eq(*uid))),
-                )
-                .filter(expenses::is_deleted.eq(false))
-                .into_boxed();
-
-            if let Some(from) = request.from {
-                query = query.filter(expenses::booking_end.ge(from));
-            }
-            if let Some(to) = request.to {
-                query = query.filter(expenses::booking_start.le(to));
-            }
-
-            query = query
-                .then_order_by(expenses::is_template.asc())
-                .then_order_by(expenses::is_unchecked.desc());
-
-            for sb in request.sort_by.iter() {
-                let asc = sb.direction.to_lowercase() == "ascending";
-                // TODO: sorting by amount would be nice
-
-                match sb.column.as_ref() {
-                    "info.title" => {
-                        if asc {
-                            query = query.then_order_by(expenses::title.asc());
-                        } else {
-                            query = query.then_order_by(expenses::title.desc());
-                        }
-                    }
-                    "info.id" => {
-                        if asc {
-                            query = query.then_order_by(expenses::id.asc());
-                        } else {
-                            query = query.then_order_by(expenses::id.desc());
-                        }
-                    }
-                    "info.store" => {
-                        if asc {
-                            query = query.then_order_by(expenses::store.asc());
-                        } else {
-                            query = query.then_order_by(expenses::store.desc());
-                        }
-                    }
-                    "info.description" => {
-                        if asc {
-                            query = query.then_order_by(expenses::description.asc());
-                        } else {
-                            query = query.then_order_by(expenses::description.desc());
-                        }
-                    }
-                    "info.comments" => {
-                        if asc {
-                            query = query.then_order_by(expenses::comments.asc());
-                        } else {
-                            query = query.then_order_by(expenses::comments.desc());
-                        }
-                    }
-                    "info.bookingStart" | "info.booking_start" => {
-                        if asc {
-                            query = query.then_order_by(expenses::booking_start.asc());
-                        } else {
-                            query = query.then_order_by(expenses::booking_start.desc());
-                        }
-                    }
-                    "info.bookingEnd" | "info.booking_end" => {
-                        if asc {
-                            query = query.then_order_by(expenses::booking_end.asc());
-                        } else {
-                            query = query.then_order_by(expenses::booking_end.desc());
-                        }
-                    }
-                    "info.isTemplate" | "info.is_template" => {
-                        if asc {
-                            query = query.then_order_by(expenses::is_template.asc());
-                        } else {
-                            query = query.then_order_by(expenses::is_template.desc());
-                        }
-                    }
-                    "info.isPreliminary" | "info.is_preliminary" => {
-                        if asc {
-                            query = query.then_order_by(expenses::is_preliminary.asc());
-                        } else {
-                            query = query.then_order_by(expenses::is_preliminary.desc());
-                        }
-                    }
-                    "info.isTaxRelevant" | "info.is_tax_relevant" => {
-                        if asc {
-                            query = query.then_order_by(expenses::is_tax_relevant.asc());
-                        } else {
-                            query = query.then_order_by(expenses::is_tax_relevant.desc());
-                        }
-                    }
-                    "info.isUnchecked" | "info.is_unchecked" => {
-                        if asc {
-                            query = query.then_order_by(expenses::is_unchecked.asc());
-                        } else {
-                            query = query.then_order_by(expenses::is_unchecked.desc());
-                        }
-                    }
-                    _ => {
-                        return Err(Status::BadRequest);
-                    }
-                }
-            }
-
-            query = query.then_order_by(expenses::id.asc());
-
-            for (column, values) in request.filter_by.iter() {
-                match column.as_ref() {
-                    "transactions" => {
-                        let values_parsed = values
-                            .into_iter()
-                            .map(|s| s.parse().ok())
-                            .collect::<Option<Vec<i32>>>()
-                            .ok_or(Status::BadRequest)?;
-                        query = query.filter(
-                            expense_transactions::account_id
-                                .eq_any(values_parsed.clone())
-                                .or(account_synchronizations::account1
-                                    .eq_any(values_parsed.clone())
-                                    .or(account_synchronizations::account2.eq_any(values_parsed))),
-                        );
-                    }
-                    "categories" => {
-                        let values_parsed = values
-                            .into_iter()
-                            .map(|s| s.parse().ok())
-                            .collect::<Option<Vec<i32>>>()
-                            .ok_or(Status::BadRequest)?;
-                        query = query.filter(
-                            expense_categories::category_id
-                                .eq_any(values_parsed.clone())
-                                .or(category_replacements::original.eq_any(values_parsed.clone())),
-                        );
-                    }
-                    "info.title" => {
-                        query = query.filter(expenses::title.eq_any(values));
-                    }
-                    "info.id" => {
-                        let values_parsed = values
-                            .into_iter()
-                            .map(|s| s.parse().ok())
-                            .collect::<Option<Vec<i32>>>()
-                            .ok_or(Status::BadRequest)?;
-
-                        query = query.filter(expenses::id.eq_any(values_parsed));
-                    }
-                    "info.store" => {
-                        query = query.filter(expenses::store.eq_any(values));
-                    }
-                    "info.description" => {
-                        query = query.filter(expenses::description.eq_any(values));
-                    }
-                    "info.comments" => {
-                        query = query.filter(expenses::comments.eq_any(values));
-                    }
-                    "info.isTemplate" | "info.is_template" => {
-                        let values_parsed = values
-                            .into_iter()
-                            .map(|s| s.parse().ok())
-                            .collect::<Option<Vec<bool>>>()
-                            .ok_or(Status::BadRequest)?;
-                        query = query.filter(expenses::is_template.eq_any(values_parsed));
-                    }
-                    "info.isPreliminary" | "info.is_preliminary" => {
-                        let values_parsed = values
-                            .into_iter()
-                            .map(|s| s.parse().ok())
-                            .collect::<Option<Vec<bool>>>()
-                            .ok_or(Status::BadRequest)?;
-                        query = query.filter(expenses::is_preliminary.eq_any(values_parsed));
-                    }
-                    "info.isTaxRelevant" | "info.is_tax_relevant" => {
-                        let values_parsed = values
-                            .into_iter()
-                            .map(|s| s.parse().ok())
-                            .collect::<Option<Vec<bool>>>()
-                            .ok_or(Status::BadRequest)?;
-                        query = query.filter(expenses::is_tax_relevant.eq_any(values_parsed));
-                    }
-                    "info.isUnchecked" | "info.is_unchecked" => {
-                        let values_parsed = values
-                            .into_iter()
-                            .map(|s| s.parse().ok())
-                            .collect::<Option<Vec<bool>>>()
-                            .ok_or(Status::BadRequest)?;
-                        query = query.filter(expenses::is_unchecked.eq_any(values_parsed));
-                    }
-                    _ => {
-                        return Err(Status::BadRequest);
-                    }
-                }
-            }
-
-            if let Some(s) = request.needle {
-                // TODO: more fields to search in (amount would be nice ...)
-
-                let s = format!("%{}%", s.trim());
-                if !s.is_empty() {
-                    query = query.filter(
-                        expenses::store
-                            .ilike(s.clone())
-                            .or(expenses::description.ilike(s.clone()))
-                            .or(expenses::title.ilike(s.clone()))
-                            .or(expenses::comments.ilike(s)),
-                    );
-                }
-            }
-
-            let (expenses, row_count) = query
-                .paginate(request.page)
-                .per_page(request.rows_per_page)
-                .load_and_count::<Expense>(c)
-                .map_err(|e| log_error_and_500(Box::new(e)))?;
-
-            let exp_ids = expenses.iter().map(|e| e.id).collect::<Vec<_>>();
-
-            let transactions = expense_transactions::table
-                .inner_join(accounts::table.on(accounts::id.eq(expense_transactions::account_id)))
-                .left_join(
-                    account_synchronizations::table.on((account_synchronizations::account1
-                        .eq(expense_transactions::account_id)
-                        .and(account_synchronizations::user1.ne(*uid)))
-                    .or(account_synchronizations::account2
-                        .eq(expense_transactions::account_id)
-                        .and(account_synchronizations::user2.ne(*uid)))),
-                )
-                .filter(
-                    expense_transactions::expense_id
-                        .eq_any(exp_ids.iter().cloned().collect::<Vec<_>>()),
-                )
-                .load::<(ExpenseTransaction, Account, Option<AccountSynchronization>)>(c)
-                .map_err(|e| log_error_and_500(Box::new(e)))?;
-
-            let categories = expense_categories::table
-                .left_join(
-                    category_replacements::table.on(category_replacements::original
-                        .eq(expense_categories::category_id)
-                        .and(category_replacements::user_id.eq(*uid))),
-                )
-                .filter(
-                    expense_categories::expense_id
-                        .eq_any(exp_ids.iter().cloned().collect::<Vec<_>>()),
-                )
-                .load::<(ExpenseCategory, Option<CategoryReplacement>)>(c)
-                .map_err(|e| log_error_and_500(Box::new(e)))?;
-
-            let receipts = expense_receipts::table
-                .filter(
-                    expense_receipts::expense_id
-                        .eq_any(exp_ids.iter().cloned().collect::<Vec<_>>()),
-                )
-                .load::<ExpenseReceipt>(c)
-                .map_err(|e| log_error_and_500(Box::new(e)))?;
-            let events = expense_events::table
-                .filter(expense_events::expense_id.eq_any(exp_ids))
-                .load::<ExpenseEvent>(c)
-                .map_err(|e| log_error_and_500(Box::new(e)))?;
+            let (expenses, row_count) = query_expenses(c, *uid, &request)?;
 
-            let rexps = expenses
-                .into_iter()
-                .map(|e| {
-                    RenderedExpense::filter_and_render(
-                        *uid,
-                        e,
-                        &transactions[..],
-                        &categories,
-                        &receipts,
-                        &events,
-                    )
-                })
-                .collect::<Vec<_>>();
+            let rexps = render_all(c, *uid, expenses)?;
 
             Ok(Json(QueryResponse {
                 records: rexps,
@@ -538,53 +860,549 @@ pub async fn get(
     uid: UserId,
     connection: DbConn,
     id: i32,
+) -> Result<Json<RenderedExpense>, Status> {
+    connection
+        .run(move |c| Ok(Json(render(c, *uid, id)?)))
+        .await
+}
+
+#[post("/expenses", data = "<expense>")]
+pub async fn create(
+    uid: UserId,
+    connection: DbConn,
+    expense: Json<ExpenseRequest>,
+) -> Result<Json<RenderedExpense>, Status> {
+    connection
+        .run(move |c| {
+            let request = expense.0;
+            validate(c, *uid, &request, None)?;
+
+            let mut info = request.info;
+            info.is_deleted = false;
+
+            let exp = mutations::insert_expense(
+                c,
+                *uid,
+                TOOL,
+                &info,
+                request.transactions.iter().map(|t| t.convert(0)).collect(),
+                request.categories.iter().map(|ec| ec.convert(0)).collect(),
+            )
+            .map_err(|e| log_error_and_500(Box::new(e)))?;
+            info!("User {} created expense {}", *uid, exp.id);
+
+            Ok(Json(render(c, *uid, exp.id)?))
+        })
+        .await
+}
+
+#[put("/expenses/<id>", data = "<expense>")]
+pub async fn update(
+    uid: UserId,
+    connection: DbConn,
+    store: State<'_, ReceiptStore>,
+    id: i32,
+    expense: Json<ExpenseRequest>,
+) -> Result<Json<RenderedExpense>, Status> {
+    let store = store.inner().clone();
+    connection
+        .run(move |c| {
+            let request = expense.0;
+            let current = render(c, *uid, id)?;
+            validate(c, *uid, &request, Some(&current))?;
+
+            let stored = mutations::load_expense_contents(c, id)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+            // undo what `RenderedExpense::render` did to the transactions and categories
+            let rendered_transactions = queries::expense_transactions_by_expense_id(c, *uid, id)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+            let transactions = request
+                .transactions
+                .iter()
+                .map(|rt| {
+                    let mut t = ExpenseTransaction {
+                        id: 0,
+                        expense_id: id,
+                        account_id: rt.account_id,
+                        date: rt.date,
+                        amount: rt.amount,
+                        fraction: rt.fraction,
+                        comments: rt.comments.clone(),
+                        statement: rt.statement.clone(),
+                    };
+
+                    if let Some(tid) = rt.id {
+                        let (st, _, acs) = rendered_transactions
+                            .iter()
+                            .find(|(st, _, _)| st.id == tid)
+                            .ok_or_else(|| {
+                                warn!("Transaction {} does not belong to expense {}", tid, id);
+                                Status::BadRequest
+                            })?;
+                        t.id = tid;
+
+                        if let Some(acs) = acs {
+                            let rendered_account = if acs.account1 == st.account_id {
+                                acs.account2
+                            } else {
+                                acs.account1
+                            };
+
+                            if rendered_account == t.account_id {
+                                t.account_id = st.account_id;
+                                if acs.invert {
+                                    t.amount = t.amount.map(|y| -y);
+                                    t.fraction = t.fraction.map(|y| -y);
+                                }
+                            }
+                        }
+                    }
+
+                    Ok(t)
+                })
+                .collect::<Result<Vec<_>, Status>>()?;
+
+            let rendered_categories = queries::expense_categories_by_expense_id(c, *uid, id)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+            let categories = request
+                .categories
+                .iter()
+                .map(|rc| {
+                    let category_id = rendered_categories
+                        .iter()
+                        .find(|(_, repl)| {
+                            repl.as_ref()
+                                .map(|r| r.replacement == rc.category_id)
+                                .unwrap_or(false)
+                        })
+                        .map(|(ec, _)| ec.category_id)
+                        .unwrap_or(rc.category_id);
+
+                    ExpenseCategory {
+                        expense_id: id,
+                        category_id,
+                        weight: rc.weight,
+                    }
+                })
+                .collect::<Vec<_>>();
+
+            // receipts can only be removed here, new ones have to be uploaded (see `receipts::upload`)
+            let receipts = match &request.receipts {
+                None => stored.receipts.clone(),
+                Some(rs) => rs
+                    .iter()
+                    .map(|rr| {
+                        stored
+                            .receipts
+                            .iter()
+                            .find(|r| Some(r.id) == rr.id && r.file_name == rr.file_name)
+                            .cloned()
+                            .ok_or_else(|| {
+                                warn!(
+                                    "Receipt {:?} ({}) is not a receipt of expense {}",
+                                    rr.id, rr.file_name, id
+                                );
+                                Status::BadRequest
+                            })
+                    })
+                    .collect::<Result<Vec<_>, Status>>()?,
+            };
+            let removed = stored
+                .receipts
+                .iter()
+                .filter(|r| !receipts.iter().any(|x| x.id == r.id))
+                .map(|r| r.file_name.clone())
+                .collect::<Vec<_>>();
+
+            let info = request.info;
+            let info = Expense {
+                id,
+                title: info.title,
+                description: info.description,
+                store: info.store,
+                comments: info.comments,
+                booking_start: info.booking_start,
+                booking_end: info.booking_end,
+                is_deleted: stored.info.is_deleted,
+                is_template: info.is_template,
+                is_preliminary: info.is_preliminary,
+                is_tax_relevant: info.is_tax_relevant,
+                is_unchecked: info.is_unchecked,
+            };
+
+            let events = mutations::update_expense(
+                c,
+                *uid,
+                TOOL,
+                mutations::ExpenseContents {
+                    info,
+                    transactions,
+                    categories,
+                    receipts,
+                },
+            )
+            .map_err(|e| log_error_and_500(Box::new(e)))?;
+            info!(
+                "User {} updated expense {} ({} events)",
+                *uid,
+                id,
+                events.len()
+            );
+
+            for file_name in removed.iter() {
+                store
+                    .remove_if_unreferenced(c, file_name)
+                    .map_err(log_error_and_500)?;
+            }
+
+            Ok(Json(render(c, *uid, id)?))
+        })
+        .await
+}
+
+#[delete("/expenses/<id>")]
+pub async fn delete(uid: UserId, connection: DbConn, id: i32) -> Result<(), Status> {
+    connection
+        .run(move |c| {
+            queries::relevant_expense_by_id(c, *uid, id)
+                .map_err(|e| log_error_and_500(Box::new(e)))?
+                .ok_or(Status::NotFound)?;
+
+            mutations::set_expense_deleted(c, *uid, TOOL, id, true)
+                .map_err(|e| log_error_and_500(Box::new(e)))?
+                .ok_or(Status::NotFound)?;
+            info!("User {} moved expense {} to the trash", *uid, id);
+
+            Ok(())
+        })
+        .await
+}
+
+#[post("/expenses/<id>/restore")]
+pub async fn restore(
+    uid: UserId,
+    connection: DbConn,
+    id: i32,
 ) -> Result<Json<RenderedExpense>, Status> {
     connection
         .run(move |c| {
-            let exp = queries::relevant_expense_by_id(c, *uid, id)
+            queries::relevant_expense_by_id(c, *uid, id)
+                .map_err(|e| log_error_and_500(Box::new(e)))?
+                .ok_or(Status::NotFound)?;
+
+            mutations::set_expense_deleted(c, *uid, TOOL, id, false)
                 .map_err(|e| log_error_and_500(Box::new(e)))?
                 .ok_or(Status::NotFound)?;
+            info!("User {} restored expense {} from the trash", *uid, id);
 
-            let transactions = queries::expense_transactions_by_expense_id(c, *uid, id)
+            Ok(Json(render(c, *uid, id)?))
+        })
+        .await
+}
+
+// deleted expenses, latest first. `count` is at most 1000
+#[get("/expenses/trash?<offset>&<count>")]
+pub async fn trash(
+    uid: UserId,
+    connection: DbConn,
+    offset: Option<i64>,
+    count: Option<i64>,
+) -> Result<Json<Vec<RenderedExpense>>, Status> {
+    let offset = offset.unwrap_or(0);
+    let count = count.unwrap_or(1000);
+    if offset < 0 || count <= 0 || count > 1000 {
+        warn!("Invalid trash page (offset {}, count {})", offset, count);
+        return Err(Status::BadRequest);
+    }
+
+    connection
+        .run(move |c| {
+            let exps = queries::deleted_expenses(c, *uid, Some(offset), Some(count))
                 .map_err(|e| log_error_and_500(Box::new(e)))?;
-            let categories = queries::expense_categories_by_expense_id(c, *uid, id)
+
+            Ok(Json(render_all(c, *uid, exps)?))
+        })
+        .await
+}
+
+// every version of the expense in the form in which it was stored, see `mutations::expense_history`
+#[get("/expenses/<id>/history")]
+pub async fn history(
+    uid: UserId,
+    connection: DbConn,
+    id: i32,
+) -> Result<Json<Vec<mutations::ExpenseVersion>>, Status> {
+    connection
+        .run(move |c| {
+            queries::relevant_expense_by_id(c, *uid, id)
+                .map_err(|e| log_error_and_500(Box::new(e)))?
+                .ok_or(Status::NotFound)?;
+
+            Ok(Json(
+                mutations::expense_history(c, id).map_err(|e| log_error_and_500(Box::new(e)))?,
+            ))
+        })
+        .await
+}
+
+// restores the version of the expense right after the event `event_id`, the trash state is not changed
+#[post("/expenses/<id>/revert/<event_id>")]
+pub async fn revert(
+    uid: UserId,
+    connection: DbConn,
+    id: i32,
+    event_id: i32,
+) -> Result<Json<RenderedExpense>, Status> {
+    connection
+        .run(move |c| {
+            let current = queries::relevant_expense_by_id(c, *uid, id)
+                .map_err(|e| log_error_and_500(Box::new(e)))?
+                .ok_or(Status::NotFound)?;
+
+            let mut contents = mutations::expense_history(c, id)
+                .map_err(|e| log_error_and_500(Box::new(e)))?
+                .into_iter()
+                .find(|v| v.event.id == event_id)
+                .ok_or(Status::NotFound)?
+                .contents
+                .ok_or_else(|| {
+                    warn!(
+                        "Version of expense {} after event {} cannot be rebuilt",
+                        id, event_id
+                    );
+                    Status::Conflict
+                })?;
+            contents.info.is_deleted = current.is_deleted;
+
+            // accounts and categories might have been removed in the meantime
+            let mut account_ids = contents
+                .transactions
+                .iter()
+                .map(|t| t.account_id)
+                .collect::<Vec<_>>();
+            account_ids.sort_unstable();
+            account_ids.dedup();
+            let account_count = accounts::table
+                .filter(accounts::id.eq_any(&account_ids))
+                .select(diesel::dsl::count(accounts::id))
+                .get_result::<i64>(c)
                 .map_err(|e| log_error_and_500(Box::new(e)))?;
-            let receipts = expense_receipts::table
-                .filter(expense_receipts::expense_id.eq(id))
-                .load::<ExpenseReceipt>(c)
+            let category_ids = contents
+                .categories
+                .iter()
+                .map(|ec| ec.category_id)
+                .collect::<Vec<_>>();
+            let category_count = categories::table
+                .filter(categories::id.eq_any(&category_ids))
+                .select(diesel::dsl::count(categories::id))
+                .get_result::<i64>(c)
                 .map_err(|e| log_error_and_500(Box::new(e)))?;
-            let events = expense_events::table
-                .filter(expense_events::expense_id.eq(id))
-                .load::<ExpenseEvent>(c)
+
+            if account_count as usize != account_ids.len()
+                || category_count as usize != category_ids.len()
+            {
+                warn!(
+                    "Version of expense {} after event {} uses accounts or categories that do not exist anymore",
+                    id, event_id
+                );
+                return Err(Status::Conflict);
+            }
+
+            let events = mutations::update_expense(c, *uid, TOOL, contents)
                 .map_err(|e| log_error_and_500(Box::new(e)))?;
+            info!(
+                "User {} reverted expense {} to event {} ({} events)",
+                *uid,
+                id,
+                event_id,
+                events.len()
+            );
 
-            let rexp =
-                RenderedExpense::render(*uid, exp, transactions, categories, receipts, events);
-            Ok(Json(rexp))
+            Ok(Json(render(c, *uid, id)?))
         })
         .await
 }
 
-// #[delete("/expenses/<id>")]
-// pub fn delete(uid: UserId, connection: DbConn, id: i32) -> Result<(), Status> {
-//     todo!() // TODO
-// }
-
-// #[put("/expenses/<id>", data = "<balances>")]
-// pub fn update(
-//     uid: UserId,
-//     connection: DbConn,
-//     id: i32,
-//     account: Json<RenderedBalance>,
-// ) -> Result<(), Status> {
-//     todo!() // TODO
-// }
-
-// #[post("/expenses", data = "<balances>")]
-// pub fn create(
-//     uid: UserId,
-//     connection: DbConn,
-//     account: Json<NewBalance>,
-// ) -> Result<Json<RenderedBalance>, Status> {
-//     todo!() // TODO
-// }
+#[post("/expenses/bulk", data = "<request>")]
+pub async fn bulk(
+    uid: UserId,
+    connection: DbConn,
+    request: Json<BulkRequest>,
+) -> Result<Json<Vec<RenderedExpense>>, Status> {
+    connection
+        .run(move |c| {
+            let request = request.0;
+
+            let mut ids = match (&request.ids, &request.query) {
+                (Some(ids), None) => {
+                    for id in ids.iter() {
+                        queries::relevant_expense_by_id(c, *uid, *id)
+                            .map_err(|e| log_error_and_500(Box::new(e)))?
+                            .ok_or(Status::NotFound)?;
+                    }
+
+                    ids.clone()
+                }
+                (None, Some(query)) => {
+                    let mut query = query.clone();
+                    query.page = 0;
+                    query.rows_per_page = BULK_QUERY_LIMIT;
+
+                    let (expenses, count) = query_expenses(c, *uid, &query)?;
+                    if count > BULK_QUERY_LIMIT {
+                        warn!(
+                            "Bulk query of user {} matches {} expenses, more than {}",
+                            *uid, count, BULK_QUERY_LIMIT
+                        );
+                        return Err(Status::BadRequest);
+                    }
+
+                    expenses.into_iter().map(|e| e.id).collect()
+                }
+                _ => {
+                    warn!("Bulk request without exactly one of ids and query");
+                    return Err(Status::BadRequest);
+                }
+            };
+            ids.sort_unstable();
+            ids.dedup();
+
+            if let Some(categories) = &request.categories {
+                validate_categories(c, *uid, categories, None)?;
+            }
+
+            // categories are shown as their replacements, see `RenderedCategory::render`
+            let replacements = category_replacements::table
+                .filter(category_replacements::user_id.eq(*uid))
+                .load::<CategoryReplacement>(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+            let rendered_id = |id: i32| {
+                replacements
+                    .iter()
+                    .find(|r| r.original == id)
+                    .map(|r| r.replacement)
+                    .unwrap_or(id)
+            };
+
+            let contents = ids
+                .iter()
+                .map(|id| {
+                    let mut contents = mutations::load_expense_contents(c, *id)?;
+                    let info = &mut contents.info;
+
+                    if let Some(x) = request.is_unchecked {
+                        info.is_unchecked = x;
+                    }
+                    if let Some(x) = request.is_tax_relevant {
+                        info.is_tax_relevant = x;
+                    }
+                    if let Some(x) = request.is_preliminary {
+                        info.is_preliminary = x;
+                    }
+                    if let Some(comments) = &request.append_comments {
+                        if info.comments.is_empty() {
+                            info.comments = comments.clone();
+                        } else {
+                            info.comments = format!("{}\n{}", info.comments, comments);
+                        }
+                    }
+
+                    if let Some(categories) = &request.categories {
+                        let stored = std::mem::take(&mut contents.categories);
+                        if !request.replace_categories {
+                            contents.categories = stored.clone();
+                        }
+
+                        for rc in categories.iter() {
+                            let category_id = stored
+                                .iter()
+                                .find(|ec| rendered_id(ec.category_id) == rc.category_id)
+                                .map(|ec| ec.category_id)
+                                .unwrap_or(rc.category_id);
+
+                            match contents
+                                .categories
+                                .iter_mut()
+                                .find(|ec| ec.category_id == category_id)
+                            {
+                                Some(ec) => ec.weight = rc.weight,
+                                None => contents.categories.push(ExpenseCategory {
+                                    expense_id: *id,
+                                    category_id,
+                                    weight: rc.weight,
+                                }),
+                            }
+                        }
+                    }
+
+                    Ok(contents)
+                })
+                .collect::<Result<Vec<_>, diesel::result::Error>>()
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+            let events = mutations::update_expenses(c, *uid, TOOL, contents)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+            info!(
+                "User {} edited {} expenses at once ({} changed)",
+                *uid,
+                ids.len(),
+                events.len()
+            );
+
+            let exps = expenses::table
+                .filter(expenses::id.eq_any(ids))
+                .order(expenses::id)
+                .load::<Expense>(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+            Ok(Json(render_all(c, *uid, exps)?))
+        })
+        .await
+}
+
+// creates a concrete expense from a template, see `mutations::instantiate_template`
+#[post("/expenses/<id>/instantiate", data = "<request>")]
+pub async fn instantiate(
+    uid: UserId,
+    connection: DbConn,
+    id: i32,
+    request: Json<TemplateRequest>,
+) -> Result<Json<RenderedExpense>, Status> {
+    connection
+        .run(move |c| {
+            let template = queries::relevant_expense_by_id(c, *uid, id)
+                .map_err(|e| log_error_and_500(Box::new(e)))?
+                .ok_or(Status::NotFound)?;
+            if !template.is_template || template.is_deleted {
+                warn!("Expense {} is not a template", id);
+                return Err(Status::BadRequest);
+            }
+
+            if request.amount.is_some() {
+                let num_amounts = expense_transactions::table
+                    .filter(expense_transactions::expense_id.eq(id))
+                    .filter(expense_transactions::amount.is_not_null())
+                    .select(diesel::dsl::count(expense_transactions::id))
+                    .get_result::<i64>(c)
+                    .map_err(|e| log_error_and_500(Box::new(e)))?;
+                if num_amounts != 1 {
+                    warn!(
+                        "Cannot override the amount of template {} with {} fixed amounts",
+                        id, num_amounts
+                    );
+                    return Err(Status::BadRequest);
+                }
+            }
+
+            let exp = mutations::instantiate_template(c, *uid, id, request.date, request.amount)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+            info!(
+                "User {} created expense {} from template {}",
+                *uid, exp.id, id
+            );
+
+            Ok(Json(render(c, *uid, exp.id)?))
+        })
+        .await
+}
@@ -1,12 +1,21 @@
 pub mod accounts;
 pub mod balances;
+pub mod budgets;
 pub mod categories;
 pub mod expenses;
 pub mod pagination;
+pub mod receipts;
+pub mod recurrences;
+pub mod replacements;
+pub mod reports;
+pub mod rules;
+pub mod settlements;
 pub mod static_files;
+pub mod synchronizations;
 pub mod user;
 mod util;
 
+use crate::receipts::ReceiptStore;
 use rocket::config::LogLevel;
 use rocket::data::{Limits, ToByteUnit};
 use rocket::figment::util::map;
@@ -18,6 +27,8 @@ pub struct Config {
     pub secret_key: Option<String>,
     pub log_level: LogLevel,
     pub database_url: String,
+    pub receipts: String,
+    pub forecast_threshold: i64,
 }
 
 pub async fn handle(config: Config) {
@@ -40,6 +51,7 @@ pub async fn handle(config: Config) {
 
     rocket::custom(rocket_config)
         .attach(DbConn::fairing())
+        .manage(ReceiptStore::new(&config.receipts))
         .manage(config)
         .mount(
             "/api/",
@@ -50,28 +62,71 @@ pub async fn handle(config: Config) {
                 user::list,
                 accounts::list,
                 accounts::get,
-                // accounts::delete,
-                // accounts::update,
-                // accounts::create,
+                accounts::create,
+                accounts::update,
+                accounts::set_hidden,
+                accounts::delete,
+                accounts::history,
                 balances::list,
                 balances::get,
                 balances::query,
                 balances::info,
-                // balances::delete,
-                // balances::update,
-                // balances::create,
+                balances::reconciliation,
+                balances::create,
+                balances::create_bulk,
+                balances::update,
+                balances::delete,
+                budgets::list,
+                budgets::get,
+                budgets::create,
+                budgets::update,
+                budgets::delete,
+                budgets::status,
                 categories::list,
                 categories::get,
-                // categories::delete,
-                // categories::update,
-                // categories::create,
+                categories::create,
+                categories::update,
+                categories::merge,
+                categories::delete,
+                replacements::list,
+                replacements::create,
+                replacements::delete,
+                replacements::suggestions,
+                rules::list,
+                rules::get,
+                rules::create,
+                rules::update,
+                rules::delete,
+                rules::test,
+                settlements::list,
+                settlements::settle,
+                synchronizations::list_invitations,
+                synchronizations::invite,
+                synchronizations::accept,
+                synchronizations::delete_invitation,
+                synchronizations::unlink,
                 expenses::list,
                 expenses::get,
                 expenses::query,
                 expenses::info,
-                // categories::delete,
-                // categories::update,
-                // categories::create,
+                expenses::create,
+                expenses::update,
+                expenses::delete,
+                expenses::restore,
+                expenses::trash,
+                expenses::history,
+                expenses::revert,
+                expenses::bulk,
+                expenses::instantiate,
+                receipts::upload,
+                receipts::download,
+                receipts::delete,
+                recurrences::list,
+                recurrences::create_template,
+                reports::category_spending,
+                reports::net_worth,
+                reports::forecast,
+                reports::tax_report,
             ],
         )
         .mount("/", routes![static_files::serve, static_files::index])
@@ -0,0 +1,177 @@
+use crate::models::*;
+use crate::mutations;
+use crate::queries;
+use crate::receipts::{self, ReceiptStore};
+use crate::schema::*;
+use crate::web::user::UserId;
+use crate::web::util::{log_error_and_500, TOOL};
+use crate::web::DbConn;
+
+use diesel::prelude::*;
+use log::{info, warn};
+use rocket::data::{Data, ToByteUnit};
+use rocket::http::{ContentType, Status};
+use rocket::State;
+use rocket_contrib::databases::diesel;
+use rocket_contrib::json::Json;
+use tokio::io::AsyncReadExt;
+
+// receipts the user may access, i.e. the ones of expenses that they can see
+fn relevant_receipt(c: &PgConnection, uid: i32, id: i32) -> Result<ExpenseReceipt, Status> {
+    let receipt = expense_receipts::table
+        .filter(expense_receipts::id.eq(id))
+        .get_result::<ExpenseReceipt>(c)
+        .optional()
+        .map_err(|e| log_error_and_500(Box::new(e)))?
+        .ok_or(Status::NotFound)?;
+
+    queries::relevant_expense_by_id(c, uid, receipt.expense_id)
+        .map_err(|e| log_error_and_500(Box::new(e)))?
+        .ok_or(Status::NotFound)?;
+
+    Ok(receipt)
+}
+
+// all files of a multipart/form-data body
+async fn read_files(data: Data, content_type: &ContentType) -> Result<Vec<Vec<u8>>, Status> {
+    let boundary = multer::parse_boundary(content_type.to_string()).map_err(|e| {
+        warn!("Receipt upload without multipart boundary: {}", e);
+        Status::BadRequest
+    })?;
+
+    let mut body = Vec::new();
+    data.open(20.mebibytes())
+        .read_to_end(&mut body)
+        .await
+        .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+    let mut multipart = multer::Multipart::new(
+        futures::stream::once(async move { Ok::<_, std::io::Error>(body) }),
+        boundary,
+    );
+
+    let mut files = Vec::new();
+    loop {
+        match multipart.next_field().await {
+            Ok(Some(field)) => {
+                if field.file_name().is_none() {
+                    continue;
+                }
+
+                let bytes = field.bytes().await.map_err(|e| {
+                    warn!("Unable to read uploaded receipt: {}", e);
+                    Status::BadRequest
+                })?;
+                files.push(bytes.to_vec());
+            }
+            Ok(None) => return Ok(files),
+            Err(e) => {
+                warn!("Invalid multipart body: {}", e);
+                return Err(Status::BadRequest);
+            }
+        }
+    }
+}
+
+// accepts any number of files in a multipart/form-data body and returns all receipts of the expense
+#[post("/expenses/<id>/receipts", data = "<data>")]
+pub async fn upload(
+    uid: UserId,
+    connection: DbConn,
+    store: State<'_, ReceiptStore>,
+    id: i32,
+    content_type: &ContentType,
+    data: Data,
+) -> Result<Json<Vec<ExpenseReceipt>>, Status> {
+    let files = read_files(data, content_type).await?;
+    if files.is_empty() {
+        warn!("Receipt upload without files");
+        return Err(Status::BadRequest);
+    }
+
+    let store = store.inner().clone();
+    connection
+        .run(move |c| {
+            queries::relevant_expense_by_id(c, *uid, id)
+                .map_err(|e| log_error_and_500(Box::new(e)))?
+                .ok_or(Status::NotFound)?;
+
+            let mut file_names = Vec::new();
+            for data in files.iter() {
+                match store
+                    .put(data)
+                    .map_err(|e| log_error_and_500(Box::new(e)))?
+                {
+                    Some(file_name) => file_names.push(file_name),
+                    None => {
+                        warn!("Receipt upload with unsupported file type");
+                        return Err(Status::UnsupportedMediaType);
+                    }
+                }
+            }
+
+            let receipts = mutations::add_receipts(c, *uid, TOOL, id, file_names)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+            info!(
+                "User {} uploaded {} receipts for expense {}",
+                *uid,
+                files.len(),
+                id
+            );
+
+            Ok(Json(receipts))
+        })
+        .await
+}
+
+#[get("/receipts/<id>")]
+pub async fn download(
+    uid: UserId,
+    connection: DbConn,
+    store: State<'_, ReceiptStore>,
+    id: i32,
+) -> Result<(ContentType, Vec<u8>), Status> {
+    let store = store.inner().clone();
+    connection
+        .run(move |c| {
+            let receipt = relevant_receipt(c, *uid, id)?;
+            let data = store.get(&receipt.file_name).map_err(|e| {
+                warn!("Unable to read receipt {}: {}", id, e);
+                Status::NotFound
+            })?;
+
+            let content_type = receipts::sniff(&data)
+                .and_then(ContentType::from_extension)
+                .unwrap_or(ContentType::Binary);
+
+            Ok((content_type, data))
+        })
+        .await
+}
+
+#[delete("/receipts/<id>")]
+pub async fn delete(
+    uid: UserId,
+    connection: DbConn,
+    store: State<'_, ReceiptStore>,
+    id: i32,
+) -> Result<(), Status> {
+    let store = store.inner().clone();
+    connection
+        .run(move |c| {
+            let receipt = relevant_receipt(c, *uid, id)?;
+
+            mutations::delete_receipt(c, *uid, TOOL, &receipt)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+            store
+                .remove_if_unreferenced(c, &receipt.file_name)
+                .map_err(log_error_and_500)?;
+            info!(
+                "User {} deleted receipt {} of expense {}",
+                *uid, id, receipt.expense_id
+            );
+
+            Ok(())
+        })
+        .await
+}
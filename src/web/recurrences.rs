@@ -0,0 +1,89 @@
+use crate::models::*;
+use crate::mutations;
+use crate::queries;
+use crate::recurrences::{self, Recurrence};
+use crate::schema::*;
+use crate::web::user::UserId;
+use crate::web::util::{log_error_and_500, TOOL};
+use crate::web::DbConn;
+
+use diesel::prelude::*;
+use log::{info, warn};
+use regex::Regex;
+use rocket::http::Status;
+use rocket_contrib::databases::diesel;
+use rocket_contrib::json::Json;
+
+// expenses of the user that seem to recur regularly
+#[get("/recurrences")]
+pub async fn list(uid: UserId, connection: DbConn) -> Result<Json<Vec<Recurrence>>, Status> {
+    connection
+        .run(move |c| {
+            let occurrences =
+                recurrences::occurrences(c, *uid).map_err(|e| log_error_and_500(Box::new(e)))?;
+
+            Ok(Json(recurrences::detect(&occurrences)))
+        })
+        .await
+}
+
+// turns a (possibly edited) recurrence into a template, copied from its latest expense, and a delivery rule for it
+#[post("/recurrences/template", data = "<recurrence>")]
+pub async fn create_template(
+    uid: UserId,
+    connection: DbConn,
+    recurrence: Json<Recurrence>,
+) -> Result<Json<DeliveryRule>, Status> {
+    connection
+        .run(move |c| {
+            let recurrence = recurrence.0;
+
+            let id = *recurrence.expense_ids.last().ok_or_else(|| {
+                warn!("Recurrence without expenses");
+                Status::BadRequest
+            })?;
+            queries::relevant_expense_by_id(c, *uid, id)
+                .map_err(|e| log_error_and_500(Box::new(e)))?
+                .ok_or(Status::NotFound)?;
+
+            if let Err(e) = Regex::new(&recurrence.statement_regex) {
+                warn!(
+                    "Recurrence with invalid regex '{}': {}",
+                    recurrence.statement_regex, e
+                );
+                return Err(Status::BadRequest);
+            }
+
+            let accs = queries::writable_account_ids(c, *uid)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+            if !accs.contains(&recurrence.account_id) {
+                warn!("Recurrence with unknown account {}", recurrence.account_id);
+                return Err(Status::BadRequest);
+            }
+
+            let rule = c
+                .transaction::<_, diesel::result::Error, _>(|| {
+                    let template = mutations::create_template(c, *uid, TOOL, id)?;
+
+                    diesel::insert_into(delivery_rules::table)
+                        .values(&NewDeliveryRule {
+                            user_id: *uid,
+                            priority: 0,
+                            template_id: template.id,
+                            account_id: Some(recurrence.account_id),
+                            amount: None,
+                            statement_regex: recurrence.statement_regex.clone(),
+                            last_match: Some(recurrence.last),
+                        })
+                        .get_result::<DeliveryRule>(c)
+                })
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+            info!(
+                "User {} created template {} and delivery rule {} from expense {}",
+                *uid, rule.template_id, rule.id, id
+            );
+
+            Ok(Json(rule))
+        })
+        .await
+}
@@ -0,0 +1,166 @@
+use crate::models::*;
+use crate::queries;
+use crate::schema::*;
+use crate::web::user::UserId;
+use crate::web::DbConn;
+
+use crate::web::util::{log_error_and_409_or_500, log_error_and_500};
+use diesel::prelude::*;
+use log::{info, warn};
+use rocket::http::Status;
+use rocket_contrib::databases::diesel;
+use rocket_contrib::json::Json;
+
+use std::collections::{HashMap, HashSet};
+
+// category names are compared case-insensitively and without surrounding whitespace
+fn normalize_name(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+#[get("/categories/replacements")]
+pub async fn list(
+    uid: UserId,
+    connection: DbConn,
+) -> Result<Json<Vec<CategoryReplacement>>, Status> {
+    connection
+        .run(move |c| {
+            Ok(Json(
+                category_replacements::table
+                    .filter(category_replacements::user_id.eq(*uid))
+                    .order(category_replacements::original)
+                    .load::<CategoryReplacement>(c)
+                    .map_err(|e| log_error_and_500(Box::new(e)))?,
+            ))
+        })
+        .await
+}
+
+#[post("/categories/replacements", data = "<replacement>")]
+pub async fn create(
+    uid: UserId,
+    connection: DbConn,
+    replacement: Json<CategoryReplacement>,
+) -> Result<Json<CategoryReplacement>, Status> {
+    connection
+        .run(move |c| {
+            let mut repl = replacement.0;
+            repl.user_id = *uid;
+
+            let owners = categories::table
+                .filter(categories::id.eq_any(vec![repl.original, repl.replacement]))
+                .select((categories::id, categories::user_id))
+                .load::<(i32, i32)>(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?
+                .into_iter()
+                .collect::<HashMap<_, _>>();
+
+            match owners.get(&repl.original) {
+                None => {
+                    warn!("Replacement of unknown category {}", repl.original);
+                    return Err(Status::BadRequest);
+                }
+                Some(owner) if *owner == *uid => {
+                    warn!(
+                        "User {} cannot replace their own category {}",
+                        *uid, repl.original
+                    );
+                    return Err(Status::BadRequest);
+                }
+                _ => {}
+            }
+            if owners.get(&repl.replacement) != Some(&*uid) {
+                warn!(
+                    "Category {} cannot be used as replacement by user {}",
+                    repl.replacement, *uid
+                );
+                return Err(Status::BadRequest);
+            }
+
+            let repl: CategoryReplacement = diesel::insert_into(category_replacements::table)
+                .values(&repl)
+                .get_result(c)
+                .map_err(log_error_and_409_or_500)?;
+            info!(
+                "User {} replaced category {} by {}",
+                *uid, repl.original, repl.replacement
+            );
+
+            Ok(Json(repl))
+        })
+        .await
+}
+
+#[delete("/categories/replacements/<original>")]
+pub async fn delete(uid: UserId, connection: DbConn, original: i32) -> Result<(), Status> {
+    connection
+        .run(move |c| {
+            let count = diesel::delete(
+                category_replacements::table
+                    .filter(category_replacements::user_id.eq(*uid))
+                    .filter(category_replacements::original.eq(original)),
+            )
+            .execute(c)
+            .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+            if count == 0 {
+                return Err(Status::NotFound);
+            }
+            info!("User {} removed replacement of category {}", *uid, original);
+
+            Ok(())
+        })
+        .await
+}
+
+// proposes replacements for all categories of users sharing a synchronized account,
+// if the user has exactly one category with the same name and did not replace them yet
+#[get("/categories/replacements/suggestions")]
+pub async fn suggestions(
+    uid: UserId,
+    connection: DbConn,
+) -> Result<Json<Vec<CategoryReplacement>>, Status> {
+    connection
+        .run(move |c| {
+            let partners =
+                queries::partner_ids(c, *uid).map_err(|e| log_error_and_500(Box::new(e)))?;
+
+            let mut users = partners.clone();
+            users.push(*uid);
+            let cs = categories::table
+                .filter(categories::user_id.eq_any(users))
+                .order(categories::id)
+                .load::<Category>(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+            let replaced = category_replacements::table
+                .filter(category_replacements::user_id.eq(*uid))
+                .select(category_replacements::original)
+                .load::<i32>(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?
+                .into_iter()
+                .collect::<HashSet<_>>();
+
+            let mut own = HashMap::<String, Vec<i32>>::new();
+            for cat in cs.iter().filter(|cat| cat.user_id == *uid) {
+                own.entry(normalize_name(&cat.name))
+                    .or_default()
+                    .push(cat.id);
+            }
+
+            Ok(Json(
+                cs.iter()
+                    .filter(|cat| cat.user_id != *uid && !replaced.contains(&cat.id))
+                    .filter_map(|cat| match own.get(&normalize_name(&cat.name)) {
+                        Some(ids) if ids.len() == 1 => Some(CategoryReplacement {
+                            user_id: *uid,
+                            original: cat.id,
+                            replacement: ids[0],
+                        }),
+                        _ => None,
+                    })
+                    .collect(),
+            ))
+        })
+        .await
+}
@@ -0,0 +1,374 @@
+use crate::models::*;
+use crate::queries;
+use crate::recurrences;
+use crate::rendering::RenderedExpense;
+use crate::reports::{
+    self, CategorizedAmount, DataPoint, Forecast, MonthlySpending, NetWorth, View,
+};
+use crate::schema::*;
+use crate::tax;
+use crate::web::rules::matched_transactions;
+use crate::web::user::UserId;
+use crate::web::util::{log_error_and_500, parse_date_param, time_steps};
+use crate::web::{Config, DbConn};
+
+use chrono::{DateTime, Duration, Utc};
+use diesel::prelude::*;
+use log::warn;
+use regex::Regex;
+use rocket::http::{ContentType, Status};
+use rocket::State;
+use rocket_contrib::databases::diesel;
+use rocket_contrib::json::Json;
+
+use std::collections::{BTreeMap, HashMap};
+
+// the user's expenses that (partly) fall into [from, to), see `queries::booked_expenses`
+fn booked_expenses(
+    c: &PgConnection,
+    uid: i32,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<RenderedExpense>, Status> {
+    let exps =
+        queries::booked_expenses(c, uid, from, to).map_err(|e| log_error_and_500(Box::new(e)))?;
+
+    queries::render_expenses(c, uid, exps).map_err(|e| log_error_and_500(Box::new(e)))
+}
+
+pub fn parse_view(view: Option<String>) -> Result<View, Status> {
+    match view {
+        Some(v) => View::parse(&v).ok_or_else(|| {
+            warn!("Invalid view '{}'", v);
+            Status::BadRequest
+        }),
+        None => Ok(View::Cash),
+    }
+}
+
+// the parts of the user's expenses (with their replaced categories) that fall into [from, to)
+pub fn categorized_expenses(
+    c: &PgConnection,
+    uid: i32,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    view: View,
+) -> Result<Vec<CategorizedAmount>, Status> {
+    // after rendering, transactions on synchronized accounts appear on the user's own accounts
+    let own = accounts::table
+        .filter(accounts::user_id.eq(uid))
+        .select(accounts::id)
+        .load::<i32>(c)
+        .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+    let mut parts = Vec::new();
+    for e in booked_expenses(c, uid, from, to)?.into_iter() {
+        let categories = e
+            .categories
+            .iter()
+            .map(|c| (c.category_id, c.weight))
+            .collect::<Vec<_>>();
+
+        let amounts = match view {
+            View::Cash => e
+                .transactions
+                .iter()
+                .zip(e.calculated_amounts.iter())
+                .filter(|(t, _)| own.contains(&t.account_id))
+                .map(|(t, amount)| (t.date, *amount))
+                .collect(),
+            View::Accrual => {
+                reports::prorate(e.total_amount, e.info.booking_start, e.info.booking_end)
+            }
+        };
+
+        parts.extend(
+            amounts
+                .into_iter()
+                .filter(|(date, _)| *date >= from && *date < to)
+                .map(|(date, amount)| CategorizedAmount {
+                    date,
+                    amount,
+                    categories: categories.clone(),
+                }),
+        );
+    }
+
+    Ok(parts)
+}
+
+// parents of the categories that the user can see: their own ones, the targets of their replacements, the ones of
+// the expenses (categories of other users may be used as well, if they are not replaced) and all their ancestors
+pub fn category_parents(
+    c: &PgConnection,
+    uid: i32,
+    expenses: &[CategorizedAmount],
+) -> Result<HashMap<i32, Option<i32>>, Status> {
+    let mut parents = categories::table
+        .filter(categories::user_id.eq(uid))
+        .select((categories::id, categories::parent))
+        .load::<(i32, Option<i32>)>(c)
+        .map_err(|e| log_error_and_500(Box::new(e)))?
+        .into_iter()
+        .collect::<HashMap<_, _>>();
+
+    let mut missing = category_replacements::table
+        .filter(category_replacements::user_id.eq(uid))
+        .select(category_replacements::replacement)
+        .load::<i32>(c)
+        .map_err(|e| log_error_and_500(Box::new(e)))?;
+    missing.extend(
+        expenses
+            .iter()
+            .flat_map(|e| e.categories.iter().map(|(id, _)| *id)),
+    );
+    missing.extend(parents.values().filter_map(|p| *p));
+
+    loop {
+        missing.retain(|id| !parents.contains_key(id));
+        missing.sort_unstable();
+        missing.dedup();
+        if missing.is_empty() {
+            return Ok(parents);
+        }
+
+        let found = categories::table
+            .filter(categories::id.eq_any(&missing))
+            .select((categories::id, categories::parent))
+            .load::<(i32, Option<i32>)>(c)
+            .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+        // categories that do not exist (anymore) have no parent
+        for id in missing.iter() {
+            parents.insert(*id, None);
+        }
+        missing = found.iter().filter_map(|(_, p)| *p).collect();
+        parents.extend(found);
+    }
+}
+
+// spending per month and category, expenses are split between their categories according to the weights.
+// defaults to the last year in the cash view.
+#[get("/reports/categories?<from>&<to>&<view>")]
+pub async fn category_spending(
+    uid: UserId,
+    connection: DbConn,
+    from: Option<String>,
+    to: Option<String>,
+    view: Option<String>,
+) -> Result<Json<Vec<MonthlySpending>>, Status> {
+    let to = parse_date_param(to)?.unwrap_or_else(Utc::now);
+    let from = parse_date_param(from)?.unwrap_or_else(|| to - Duration::days(365));
+    let view = parse_view(view)?;
+
+    connection
+        .run(move |c| {
+            let exps = categorized_expenses(c, *uid, from, to, view)?;
+            let parents = category_parents(c, *uid, &exps)?;
+
+            Ok(Json(reports::category_spending(&exps, &parents)))
+        })
+        .await
+}
+
+// net worth over time, computed from the balances of all of the user's accounts and broken down by their properties
+#[get("/reports/net-worth?<from>&<to>&<resolution>")]
+pub async fn net_worth(
+    uid: UserId,
+    connection: DbConn,
+    from: Option<String>,
+    to: Option<String>,
+    resolution: Option<String>,
+) -> Result<Json<Vec<NetWorth>>, Status> {
+    let dates = time_steps(from, to, resolution)?;
+
+    connection
+        .run(move |c| {
+            let accs = accounts::table
+                .filter(accounts::user_id.eq(*uid))
+                .order(accounts::id)
+                .load::<Account>(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+            let mut histories = Vec::new();
+            for acc in accs.into_iter() {
+                let flows = queries::account_flows(c, *uid, acc.id)
+                    .map_err(|e| log_error_and_500(Box::new(e)))?;
+                let checkpoints = queries::account_checkpoints(c, acc.id)
+                    .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+                let history = reports::balance_history(&checkpoints, &flows, &dates);
+                histories.push((acc, history));
+            }
+
+            Ok(Json(reports::net_worth(&histories, &dates)))
+        })
+        .await
+}
+
+// the dates at which a template is expected to be used after `now` (and up to `end`), based on the dates at which
+// its delivery rules matched so far
+fn expected_dates(
+    mut dates: Vec<DateTime<Utc>>,
+    now: DateTime<Utc>,
+    end: DateTime<Utc>,
+) -> Vec<DateTime<Utc>> {
+    dates.sort();
+    dates.dedup_by(|a, b| (*a - *b).num_days() == 0);
+
+    let interval = match recurrences::detect_interval(&dates) {
+        Some(interval) => interval,
+        None => return Vec::new(),
+    };
+
+    let last = *dates.last().unwrap();
+    let mut expected = Vec::new();
+    let mut n = 1;
+    let mut date = interval.nth(last, n);
+    while date <= end {
+        if date > now {
+            expected.push(date);
+        }
+        n += 1;
+        date = interval.nth(last, n);
+    }
+
+    expected
+}
+
+// balances of the user's accounts projected `months` (default: 6) into the future, starting with the current balance
+// and adding the templates of the delivery rules at their usual intervals. Points where the balance is below zero or
+// `threshold` (in cents, default: `forecast_threshold` of the server config) are flagged.
+// always in the cash view, since it is based on balances.
+#[get("/reports/forecast?<months>&<threshold>")]
+pub async fn forecast(
+    uid: UserId,
+    connection: DbConn,
+    config: State<'_, Config>,
+    months: Option<u32>,
+    threshold: Option<i64>,
+) -> Result<Json<Vec<Forecast>>, Status> {
+    let months = months.unwrap_or(6);
+    if months > 120 {
+        warn!("Forecast for too many months ({})", months);
+        return Err(Status::BadRequest);
+    }
+    let threshold = threshold.unwrap_or(config.forecast_threshold);
+
+    let now = Utc::now();
+    let end = reports::add_months(now, months);
+
+    connection
+        .run(move |c| {
+            let rules = delivery_rules::table
+                .filter(delivery_rules::user_id.eq(*uid))
+                .load::<DeliveryRule>(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+            // multiple rules may use the same template, it is still only expected once per interval
+            let mut templates = BTreeMap::<i32, Vec<DateTime<Utc>>>::new();
+            for rule in rules.into_iter() {
+                let regex = match Regex::new(&rule.statement_regex) {
+                    Ok(regex) => regex,
+                    Err(e) => {
+                        warn!("Delivery rule {} has an invalid regex: {}", rule.id, e);
+                        continue;
+                    }
+                };
+                let ts = matched_transactions(c, *uid, rule.account_id, rule.amount, &regex)?;
+
+                let matches = templates.entry(rule.template_id).or_default();
+                matches.extend(ts.iter().map(|t| t.date));
+                matches.extend(rule.last_match);
+            }
+
+            // expected changes per account
+            let mut changes = HashMap::<i32, Vec<(DateTime<Utc>, i32, i64)>>::new();
+            for (template_id, matches) in templates.into_iter() {
+                let template = match queries::relevant_expense_by_id(c, *uid, template_id)
+                    .map_err(|e| log_error_and_500(Box::new(e)))?
+                {
+                    Some(t) if t.is_template && !t.is_deleted => t,
+                    _ => continue,
+                };
+
+                let dates = expected_dates(matches, now, end);
+                let rendered = queries::render_expenses(c, *uid, vec![template])
+                    .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+                for e in rendered.into_iter() {
+                    for (t, amount) in e.transactions.iter().zip(e.calculated_amounts.iter()) {
+                        let account_changes = changes.entry(t.account_id).or_default();
+                        account_changes.extend(dates.iter().map(|d| (*d, template_id, *amount)));
+                    }
+                }
+            }
+
+            let accs = accounts::table
+                .filter(accounts::user_id.eq(*uid))
+                .select(accounts::id)
+                .order(accounts::id)
+                .load::<i32>(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+            let mut forecasts = Vec::new();
+            for id in accs.into_iter() {
+                let flows = queries::account_flows(c, *uid, id)
+                    .map_err(|e| log_error_and_500(Box::new(e)))?;
+                let checkpoints = queries::account_checkpoints(c, id)
+                    .map_err(|e| log_error_and_500(Box::new(e)))?;
+                let start = reports::balance_history(&checkpoints, &flows, &[now])
+                    .pop()
+                    .unwrap_or(DataPoint {
+                        date: now,
+                        amount: 0,
+                    });
+
+                let account_changes = changes.remove(&id).unwrap_or_default();
+                forecasts.push(reports::forecast(id, start, &account_changes, threshold));
+            }
+
+            Ok(Json(forecasts))
+        })
+        .await
+}
+
+// the tax relevant expenses of a year grouped by category, as json (default), csv or pdf.
+// there is no `View`: expenses are listed whole in the year that their booking period starts in.
+#[get("/reports/tax/<year>?<format>")]
+pub async fn tax_report(
+    uid: UserId,
+    connection: DbConn,
+    year: i32,
+    format: Option<String>,
+) -> Result<(ContentType, Vec<u8>), Status> {
+    connection
+        .run(move |c| {
+            let report = tax::tax_report(c, *uid, year)
+                .map_err(|e| log_error_and_500(Box::new(e)))?
+                .ok_or_else(|| {
+                    warn!("Tax report for invalid year {}", year);
+                    Status::BadRequest
+                })?;
+
+            match format.as_deref().unwrap_or("json") {
+                "json" => Ok((
+                    ContentType::JSON,
+                    serde_json::to_vec(&report).map_err(|e| log_error_and_500(Box::new(e)))?,
+                )),
+                "csv" => Ok((
+                    ContentType::CSV,
+                    tax::to_csv(&report).map_err(log_error_and_500)?,
+                )),
+                "pdf" => Ok((
+                    ContentType::PDF,
+                    tax::to_pdf(&report).map_err(log_error_and_500)?,
+                )),
+                f => {
+                    warn!("Invalid format '{}'", f);
+                    Err(Status::BadRequest)
+                }
+            }
+        })
+        .await
+}
@@ -0,0 +1,211 @@
+use crate::models::*;
+use crate::queries;
+use crate::rendering::RenderedTransaction;
+use crate::schema::*;
+use crate::web::user::UserId;
+use crate::web::util::log_error_and_500;
+use crate::web::DbConn;
+
+use diesel::prelude::*;
+use log::{info, warn};
+use regex::Regex;
+use rocket::http::Status;
+use rocket_contrib::databases::diesel;
+use rocket_contrib::json::Json;
+
+fn validate(
+    c: &PgConnection,
+    uid: i32,
+    template_id: i32,
+    account_id: Option<i32>,
+    statement_regex: &str,
+) -> Result<Regex, Status> {
+    let regex = Regex::new(statement_regex).map_err(|e| {
+        warn!(
+            "Delivery rule with invalid regex '{}': {}",
+            statement_regex, e
+        );
+        Status::BadRequest
+    })?;
+
+    let template = queries::relevant_expense_by_id(c, uid, template_id)
+        .map_err(|e| log_error_and_500(Box::new(e)))?;
+    if !template.map_or(false, |t| t.is_template) {
+        warn!("Delivery rule with unknown template {}", template_id);
+        return Err(Status::BadRequest);
+    }
+
+    if let Some(account_id) = account_id {
+        let accs =
+            queries::writable_account_ids(c, uid).map_err(|e| log_error_and_500(Box::new(e)))?;
+        if !accs.contains(&account_id) {
+            warn!("Delivery rule with unknown account {}", account_id);
+            return Err(Status::BadRequest);
+        }
+    }
+
+    Ok(regex)
+}
+
+// transactions of the user (as they are rendered for them) that match the conditions of a rule, latest first
+pub fn matched_transactions(
+    c: &PgConnection,
+    uid: i32,
+    account_id: Option<i32>,
+    amount: Option<i64>,
+    regex: &Regex,
+) -> Result<Vec<ExpenseTransaction>, Status> {
+    // rendered transactions have to be on accounts of the user, never on those of strangers
+    let accs = queries::writable_account_ids(c, uid).map_err(|e| log_error_and_500(Box::new(e)))?;
+
+    let mut ts = queries::renderable_transactions(c, uid)
+        .map_err(|e| log_error_and_500(Box::new(e)))?
+        .into_iter()
+        .map(|(t, acs)| RenderedTransaction::render(t, acs.as_ref()))
+        .filter(|t| accs.contains(&t.account_id))
+        .filter(|t| account_id.map_or(true, |a| a == t.account_id))
+        .filter(|t| amount.is_none() || amount == t.amount)
+        .filter(|t| regex.is_match(&t.statement))
+        .collect::<Vec<_>>();
+    ts.sort_by_key(|t| std::cmp::Reverse(t.date));
+
+    Ok(ts)
+}
+
+fn own_rule(c: &PgConnection, uid: i32, id: i32) -> Result<DeliveryRule, Status> {
+    delivery_rules::table
+        .filter(delivery_rules::id.eq(id))
+        .filter(delivery_rules::user_id.eq(uid))
+        .get_result::<DeliveryRule>(c)
+        .optional()
+        .map_err(|e| log_error_and_500(Box::new(e)))?
+        .ok_or(Status::NotFound)
+}
+
+#[get("/rules")]
+pub async fn list(uid: UserId, connection: DbConn) -> Result<Json<Vec<DeliveryRule>>, Status> {
+    connection
+        .run(move |c| {
+            Ok(Json(
+                delivery_rules::table
+                    .filter(delivery_rules::user_id.eq(*uid))
+                    .order((delivery_rules::priority, delivery_rules::id))
+                    .load::<DeliveryRule>(c)
+                    .map_err(|e| log_error_and_500(Box::new(e)))?,
+            ))
+        })
+        .await
+}
+
+#[get("/rules/<id>")]
+pub async fn get(uid: UserId, connection: DbConn, id: i32) -> Result<Json<DeliveryRule>, Status> {
+    connection
+        .run(move |c| Ok(Json(own_rule(c, *uid, id)?)))
+        .await
+}
+
+#[post("/rules", data = "<rule>")]
+pub async fn create(
+    uid: UserId,
+    connection: DbConn,
+    rule: Json<NewDeliveryRule>,
+) -> Result<Json<DeliveryRule>, Status> {
+    connection
+        .run(move |c| {
+            let mut rule = rule.0;
+            rule.user_id = *uid;
+            rule.last_match = None;
+            validate(
+                c,
+                *uid,
+                rule.template_id,
+                rule.account_id,
+                &rule.statement_regex,
+            )?;
+
+            let rule: DeliveryRule = diesel::insert_into(delivery_rules::table)
+                .values(&rule)
+                .get_result(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+            info!("User {} created delivery rule {}", *uid, rule.id);
+
+            Ok(Json(rule))
+        })
+        .await
+}
+
+#[put("/rules/<id>", data = "<rule>")]
+pub async fn update(
+    uid: UserId,
+    connection: DbConn,
+    id: i32,
+    rule: Json<DeliveryRule>,
+) -> Result<Json<DeliveryRule>, Status> {
+    connection
+        .run(move |c| {
+            let current = own_rule(c, *uid, id)?;
+
+            let mut rule = rule.0;
+            rule.id = id;
+            rule.user_id = *uid;
+            rule.last_match = current.last_match;
+            validate(
+                c,
+                *uid,
+                rule.template_id,
+                rule.account_id,
+                &rule.statement_regex,
+            )?;
+
+            let rule = diesel::update(&rule)
+                .set(&rule)
+                .get_result::<DeliveryRule>(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+            info!("User {} updated delivery rule {}", *uid, id);
+
+            Ok(Json(rule))
+        })
+        .await
+}
+
+#[delete("/rules/<id>")]
+pub async fn delete(uid: UserId, connection: DbConn, id: i32) -> Result<(), Status> {
+    connection
+        .run(move |c| {
+            let rule = own_rule(c, *uid, id)?;
+
+            diesel::delete(&rule)
+                .execute(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+            info!("User {} deleted delivery rule {}", *uid, id);
+
+            Ok(())
+        })
+        .await
+}
+
+// transactions of the user that a (not necessarily saved) rule would have matched
+#[post("/rules/test?<count>", data = "<rule>")]
+pub async fn test(
+    uid: UserId,
+    connection: DbConn,
+    rule: Json<NewDeliveryRule>,
+    count: Option<usize>,
+) -> Result<Json<Vec<ExpenseTransaction>>, Status> {
+    connection
+        .run(move |c| {
+            let regex = validate(
+                c,
+                *uid,
+                rule.template_id,
+                rule.account_id,
+                &rule.statement_regex,
+            )?;
+
+            let mut ts = matched_transactions(c, *uid, rule.account_id, rule.amount, &regex)?;
+            ts.truncate(count.unwrap_or(usize::MAX));
+
+            Ok(Json(ts))
+        })
+        .await
+}
@@ -0,0 +1,181 @@
+use crate::models::*;
+use crate::mutations;
+use crate::queries;
+use crate::schema::*;
+use crate::web::user::UserId;
+use crate::web::util::{log_error_and_500, parse_date_param, TOOL};
+use crate::web::DbConn;
+
+use chrono::{DateTime, Duration, Utc};
+use diesel::prelude::*;
+use log::{info, warn};
+use rocket::http::Status;
+use rocket_contrib::databases::diesel;
+use rocket_contrib::json::Json;
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AccountSettlement {
+    pub account_id: i32, // the user's own synchronized account
+    pub amount: i64,
+}
+
+// the balance between the user and a partner: positive amounts are owed by the partner to the user
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Settlement {
+    pub partner_id: i32,
+    pub amount: i64,
+    pub accounts: Vec<AccountSettlement>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SettlementRequest {
+    pub account_id: i32,
+    pub payment_account_id: Option<i32>, // the account through which the money was transferred, if any
+    pub date: Option<DateTime<Utc>>,
+}
+
+// sum of all (shared) transactions on the synchronized account in [from, to), as seen by the user
+fn account_balance(
+    c: &PgConnection,
+    uid: i32,
+    account_id: i32,
+    from: Option<DateTime<Utc>>,
+    to: DateTime<Utc>,
+) -> Result<i64, Status> {
+    Ok(queries::account_flows(c, uid, account_id)
+        .map_err(|e| log_error_and_500(Box::new(e)))?
+        .into_iter()
+        .filter(|(date, _)| from.map_or(true, |from| *date >= from) && *date < to)
+        .map(|(_, amount)| amount)
+        .sum())
+}
+
+// nets the transactions on the user's synchronized accounts per partner, by default over all time
+#[get("/settlements?<from>&<to>")]
+pub async fn list(
+    uid: UserId,
+    connection: DbConn,
+    from: Option<String>,
+    to: Option<String>,
+) -> Result<Json<Vec<Settlement>>, Status> {
+    let from = parse_date_param(from)?;
+    let to = parse_date_param(to)?.unwrap_or_else(Utc::now);
+
+    connection
+        .run(move |c| {
+            let syncs = account_synchronizations::table
+                .filter(
+                    account_synchronizations::user1
+                        .eq(*uid)
+                        .or(account_synchronizations::user2.eq(*uid)),
+                )
+                .order(account_synchronizations::account1)
+                .load::<AccountSynchronization>(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+
+            let mut partners = BTreeMap::<i32, Vec<AccountSettlement>>::new();
+            for sync in syncs.into_iter() {
+                let (account_id, partner_id) = if sync.user1 == *uid {
+                    (sync.account1, sync.user2)
+                } else {
+                    (sync.account2, sync.user1)
+                };
+
+                let amount = account_balance(c, *uid, account_id, from, to)?;
+                partners
+                    .entry(partner_id)
+                    .or_default()
+                    .push(AccountSettlement { account_id, amount });
+            }
+
+            Ok(Json(
+                partners
+                    .into_iter()
+                    .map(|(partner_id, accounts)| Settlement {
+                        partner_id,
+                        amount: accounts.iter().map(|a| a.amount).sum(),
+                        accounts,
+                    })
+                    .collect(),
+            ))
+        })
+        .await
+}
+
+// records a payment that brings the balance of the synchronized account back to zero
+#[post("/settlements", data = "<request>")]
+pub async fn settle(
+    uid: UserId,
+    connection: DbConn,
+    request: Json<SettlementRequest>,
+) -> Result<Json<Expense>, Status> {
+    connection
+        .run(move |c| {
+            let request = request.0;
+            let date = request.date.unwrap_or_else(Utc::now);
+
+            let owner = accounts::table
+                .find(request.account_id)
+                .select(accounts::user_id)
+                .get_result::<i32>(c)
+                .optional()
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+            let sync = queries::synchronization_by_account_id(c, request.account_id)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+            if owner != Some(*uid) || sync.is_none() {
+                warn!(
+                    "Settlement of unknown synchronized account {}",
+                    request.account_id
+                );
+                return Err(Status::BadRequest);
+            }
+
+            if let Some(payment_account_id) = request.payment_account_id {
+                let owner = accounts::table
+                    .find(payment_account_id)
+                    .select(accounts::user_id)
+                    .get_result::<i32>(c)
+                    .optional()
+                    .map_err(|e| log_error_and_500(Box::new(e)))?;
+                if owner != Some(*uid) || payment_account_id == request.account_id {
+                    warn!(
+                        "Settlement with invalid payment account {}",
+                        payment_account_id
+                    );
+                    return Err(Status::BadRequest);
+                }
+            }
+
+            // everything up to (and including) the date of the settlement
+            let to = date + Duration::nanoseconds(1);
+            let amount = account_balance(c, *uid, request.account_id, None, to)?;
+            if amount == 0 {
+                warn!("Account {} is already settled", request.account_id);
+                return Err(Status::Conflict);
+            }
+
+            let exp = mutations::record_settlement(
+                c,
+                *uid,
+                TOOL,
+                request.account_id,
+                request.payment_account_id,
+                amount,
+                date,
+            )
+            .map_err(|e| log_error_and_500(Box::new(e)))?;
+            info!(
+                "User {} settled {} on account {} with expense {}",
+                *uid, amount, request.account_id, exp.id
+            );
+
+            Ok(Json(exp))
+        })
+        .await
+}
@@ -0,0 +1,230 @@
+use crate::models::*;
+use crate::queries;
+use crate::schema::*;
+use crate::web::user::UserId;
+use crate::web::util::{log_error_and_409_or_500, log_error_and_500};
+use crate::web::DbConn;
+
+use diesel::prelude::*;
+use log::{info, warn};
+use rocket::http::Status;
+use rocket_contrib::databases::diesel;
+use rocket_contrib::json::Json;
+
+// each account can be synchronized with at most one other account
+fn ensure_unsynchronized(c: &PgConnection, account_id: i32) -> Result<(), Status> {
+    match queries::synchronization_by_account_id(c, account_id)
+        .map_err(|e| log_error_and_500(Box::new(e)))?
+    {
+        Some(sync) => {
+            warn!(
+                "Account {} is already synchronized ({} <-> {})",
+                account_id, sync.account1, sync.account2
+            );
+            Err(Status::Conflict)
+        }
+        None => Ok(()),
+    }
+}
+
+fn invitation(c: &PgConnection, uid: i32, id: i32) -> Result<AccountSyncInvitation, Status> {
+    account_sync_invitations::table
+        .filter(account_sync_invitations::id.eq(id))
+        .filter(
+            account_sync_invitations::inviter
+                .eq(uid)
+                .or(account_sync_invitations::invitee.eq(uid)),
+        )
+        .get_result::<AccountSyncInvitation>(c)
+        .optional()
+        .map_err(|e| log_error_and_500(Box::new(e)))?
+        .ok_or(Status::NotFound)
+}
+
+// invitations sent and received by the user
+#[get("/synchronizations/invitations")]
+pub async fn list_invitations(
+    uid: UserId,
+    connection: DbConn,
+) -> Result<Json<Vec<AccountSyncInvitation>>, Status> {
+    connection
+        .run(move |c| {
+            Ok(Json(
+                account_sync_invitations::table
+                    .filter(
+                        account_sync_invitations::inviter
+                            .eq(*uid)
+                            .or(account_sync_invitations::invitee.eq(*uid)),
+                    )
+                    .order(account_sync_invitations::id)
+                    .load::<AccountSyncInvitation>(c)
+                    .map_err(|e| log_error_and_500(Box::new(e)))?,
+            ))
+        })
+        .await
+}
+
+// proposes to synchronize `account` of the user with `otherAccount` of another user
+#[post("/synchronizations/invitations", data = "<invitation>")]
+pub async fn invite(
+    uid: UserId,
+    connection: DbConn,
+    invitation: Json<NewAccountSyncInvitation>,
+) -> Result<Json<AccountSyncInvitation>, Status> {
+    connection
+        .run(move |c| {
+            let mut inv = invitation.0;
+            inv.inviter = *uid;
+
+            let owners = accounts::table
+                .filter(accounts::id.eq_any(vec![inv.account, inv.other_account]))
+                .select((accounts::id, accounts::user_id))
+                .load::<(i32, i32)>(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+            let owner = |id| owners.iter().find(|(a, _)| *a == id).map(|(_, u)| *u);
+
+            if owner(inv.account) != Some(*uid) {
+                warn!(
+                    "User {} cannot synchronize account {} of another user",
+                    *uid, inv.account
+                );
+                return Err(Status::BadRequest);
+            }
+            inv.invitee = match owner(inv.other_account) {
+                Some(other) if other != *uid => other,
+                _ => {
+                    warn!(
+                        "Account {} cannot be synchronized with account {}",
+                        inv.account, inv.other_account
+                    );
+                    return Err(Status::BadRequest);
+                }
+            };
+
+            ensure_unsynchronized(c, inv.account)?;
+            ensure_unsynchronized(c, inv.other_account)?;
+
+            let inv: AccountSyncInvitation = diesel::insert_into(account_sync_invitations::table)
+                .values(&inv)
+                .get_result(c)
+                .map_err(log_error_and_409_or_500)?;
+            info!(
+                "User {} invited user {} to synchronize accounts {} and {}",
+                *uid, inv.invitee, inv.account, inv.other_account
+            );
+
+            Ok(Json(inv))
+        })
+        .await
+}
+
+// the invitee accepts and decides whether amounts are inverted between both accounts
+#[post("/synchronizations/invitations/<id>/accept", data = "<invert>")]
+pub async fn accept(
+    uid: UserId,
+    connection: DbConn,
+    id: i32,
+    invert: Json<bool>,
+) -> Result<Json<AccountSynchronization>, Status> {
+    connection
+        .run(move |c| {
+            let inv = invitation(c, *uid, id)?;
+            if inv.invitee != *uid {
+                warn!("User {} cannot accept their own invitation {}", *uid, id);
+                return Err(Status::Forbidden);
+            }
+
+            ensure_unsynchronized(c, inv.account)?;
+            ensure_unsynchronized(c, inv.other_account)?;
+
+            // the database requires account1 < account2
+            let sync = if inv.account < inv.other_account {
+                AccountSynchronization {
+                    account1: inv.account,
+                    account2: inv.other_account,
+                    user1: inv.inviter,
+                    user2: inv.invitee,
+                    invert: invert.0,
+                }
+            } else {
+                AccountSynchronization {
+                    account1: inv.other_account,
+                    account2: inv.account,
+                    user1: inv.invitee,
+                    user2: inv.inviter,
+                    invert: invert.0,
+                }
+            };
+
+            let sync = c
+                .transaction::<_, diesel::result::Error, _>(|| {
+                    let sync = diesel::insert_into(account_synchronizations::table)
+                        .values(&sync)
+                        .get_result::<AccountSynchronization>(c)?;
+
+                    // other invitations for these accounts cannot be accepted anymore
+                    let accs = vec![sync.account1, sync.account2];
+                    diesel::delete(
+                        account_sync_invitations::table.filter(
+                            account_sync_invitations::account
+                                .eq_any(accs.clone())
+                                .or(account_sync_invitations::other_account.eq_any(accs)),
+                        ),
+                    )
+                    .execute(c)?;
+
+                    Ok(sync)
+                })
+                .map_err(log_error_and_409_or_500)?;
+            info!(
+                "User {} accepted invitation {}, synchronizing accounts {} and {}",
+                *uid, id, sync.account1, sync.account2
+            );
+
+            Ok(Json(sync))
+        })
+        .await
+}
+
+// withdraws (inviter) or declines (invitee) an invitation
+#[delete("/synchronizations/invitations/<id>")]
+pub async fn delete_invitation(uid: UserId, connection: DbConn, id: i32) -> Result<(), Status> {
+    connection
+        .run(move |c| {
+            let inv = invitation(c, *uid, id)?;
+
+            diesel::delete(&inv)
+                .execute(c)
+                .map_err(|e| log_error_and_500(Box::new(e)))?;
+            info!("User {} deleted invitation {}", *uid, id);
+
+            Ok(())
+        })
+        .await
+}
+
+// either side can end a synchronization, both accounts keep the expenses that are stored on them
+#[delete("/synchronizations/<account_id>")]
+pub async fn unlink(uid: UserId, connection: DbConn, account_id: i32) -> Result<(), Status> {
+    connection
+        .run(move |c| {
+            let sync = queries::synchronization_by_account_id(c, account_id)
+                .map_err(|e| log_error_and_500(Box::new(e)))?
+                .filter(|s| s.user1 == *uid || s.user2 == *uid)
+                .ok_or(Status::NotFound)?;
+
+            diesel::delete(
+                account_synchronizations::table
+                    .filter(account_synchronizations::account1.eq(sync.account1)),
+            )
+            .execute(c)
+            .map_err(|e| log_error_and_500(Box::new(e)))?;
+            info!(
+                "User {} unlinked accounts {} and {}",
+                *uid, sync.account1, sync.account2
+            );
+
+            Ok(())
+        })
+        .await
+}
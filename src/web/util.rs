@@ -1,16 +1,131 @@
-use chrono::{DateTime, Utc};
-use log::error;
+use crate::reports::{self, Resolution};
+
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use log::{error, warn};
 use rocket::http::Status;
 use serde::{Deserialize, Serialize};
 
 use std::collections::HashMap;
 use std::error::Error;
 
+// unique constraint violations are caused by the request, everything else is our fault
+pub fn log_error_and_409_or_500(e: DieselError) -> Status {
+    match e {
+        DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, info) => {
+            warn!("{}", info.message());
+            Status::Conflict
+        }
+        e => log_error_and_500(Box::new(e)),
+    }
+}
+
+// used as `tool` of the expense events that are caused by requests to the api
+pub const TOOL: &str = "web";
+
 pub fn log_error_and_500(e: Box<dyn Error>) -> Status {
     error!("{}", e);
     Status::InternalServerError
 }
 
+// dates in query strings, either rfc 3339 or just the day (e.g. `2021-02-15`, meaning midnight utc)
+pub fn parse_date(s: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(s)
+        .map(|d| d.with_timezone(&Utc))
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(s, "%Y-%m-%d")
+                .ok()
+                .map(|d| DateTime::<Utc>::from_utc(d.and_hms(0, 0, 0), Utc))
+        })
+}
+
+// optional date parameters of a query string, invalid dates are rejected instead of ignored
+pub fn parse_date_param(s: Option<String>) -> Result<Option<DateTime<Utc>>, Status> {
+    match s {
+        Some(s) => parse_date(&s).map(Some).ok_or_else(|| {
+            warn!("Invalid date '{}'", s);
+            Status::BadRequest
+        }),
+        None => Ok(None),
+    }
+}
+
+// the dates of a timeline given by query parameters, defaults to daily values of the last year
+pub fn time_steps(
+    from: Option<String>,
+    to: Option<String>,
+    resolution: Option<String>,
+) -> Result<Vec<DateTime<Utc>>, Status> {
+    let to = parse_date_param(to)?.unwrap_or_else(Utc::now);
+    let from = parse_date_param(from)?.unwrap_or_else(|| to - Duration::days(365));
+    let resolution = match resolution {
+        Some(r) => Resolution::parse(&r).ok_or_else(|| {
+            warn!("Invalid resolution '{}'", r);
+            Status::BadRequest
+        })?,
+        None => Resolution::Day,
+    };
+
+    // checked before the dates are created, months are counted as short as possible
+    let days = match resolution {
+        Resolution::Day => 1,
+        Resolution::Week => 7,
+        Resolution::Month => 28,
+    };
+    let count = (to - from).num_days() / days + 1;
+    if count > 10000 {
+        warn!("Request for too many data points ({})", count);
+        return Err(Status::BadRequest);
+    }
+
+    Ok(reports::time_steps(from, to, resolution))
+}
+
+// colors are stored as css hex colors, i.e. `#rgb` or `#rrggbb`
+pub fn is_valid_color(color: &str) -> bool {
+    color.starts_with('#')
+        && (color.len() == 4 || color.len() == 7)
+        && color[1..].chars().all(|c| c.is_ascii_hexdigit())
+}
+
+// removes whitespace and verifies the check digits, returns `None` if this is not a valid iban
+pub fn normalize_iban(iban: &str) -> Option<String> {
+    let iban = iban
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .collect::<String>()
+        .to_uppercase();
+
+    if iban.len() < 15
+        || iban.len() > 34
+        || !iban.chars().all(|c| c.is_ascii_alphanumeric())
+        || !iban[..2].chars().all(|c| c.is_ascii_alphabetic())
+        || !iban[2..4].chars().all(|c| c.is_ascii_digit())
+    {
+        return None;
+    }
+
+    // move the first four characters to the end, replace letters by numbers (A = 10, ..., Z = 35) and calculate mod 97
+    let remainder = iban[4..]
+        .chars()
+        .chain(iban[..4].chars())
+        .fold(0, |acc, c| {
+            let d = c.to_digit(36).unwrap();
+            if d < 10 {
+                (acc * 10 + d) % 97
+            } else {
+                (acc * 100 + d) % 97
+            }
+        });
+
+    if remainder == 1 {
+        Some(iban)
+    } else {
+        None
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct SortBy {
@@ -44,3 +159,44 @@ pub struct InfoResponse {
     pub total_record_count: i64,
     pub filter_hints: HashMap<String, Vec<String>>, // lists of some possible filter values for some columns
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_iban_removes_spaces_and_uppercases() {
+        assert_eq!(
+            normalize_iban("DE89 3704 0044 0532 0130 00").as_deref(),
+            Some("DE89370400440532013000")
+        );
+        assert_eq!(
+            normalize_iban("gb82west12345698765432").as_deref(),
+            Some("GB82WEST12345698765432")
+        );
+    }
+
+    #[test]
+    fn normalize_iban_rejects_invalid_ibans() {
+        assert_eq!(normalize_iban("DE88 3704 0044 0532 0130 00"), None); // check digits
+        assert_eq!(normalize_iban("DE89 3704 0044"), None); // too short
+        assert_eq!(normalize_iban("DE89-3704-0044-0532-0130-00"), None);
+    }
+
+    #[test]
+    fn time_steps_rejects_long_timelines() {
+        let steps = |from: &str, to: &str, resolution: &str| {
+            time_steps(Some(from.into()), Some(to.into()), Some(resolution.into()))
+        };
+
+        assert_eq!(
+            steps("2021-01-01", "2021-12-01", "month").unwrap().len(),
+            12
+        );
+        assert_eq!(
+            steps("0001-01-01", "9999-12-31", "day"),
+            Err(Status::BadRequest)
+        );
+        assert!(steps("1900-01-01", "2099-12-31", "month").is_ok());
+    }
+}